@@ -68,6 +68,7 @@ fn benchmark_buffers(c: &mut Criterion) {
                     config.sinks["out"].buffer = BufferConfig::Disk {
                         max_size: 1_000_000,
                         when_full: Default::default(),
+                        max_age_secs: None,
                     }
                     .into();
                     config.data_dir = Some(data_dir.clone());
@@ -105,6 +106,7 @@ fn benchmark_buffers(c: &mut Criterion) {
                     config.sinks["out"].buffer = BufferConfig::Disk {
                         max_size: 10_000,
                         when_full: Default::default(),
+                        max_age_secs: None,
                     };
                     config.data_dir = Some(data_dir2.clone());
 
@@ -3,7 +3,7 @@
 //! This subscriber takes another subscriber like `tracing-fmt` and wraps it
 //! with this basic subscriber. It will enable all spans and events that match the
 //! metric capturing criteria. This means every span is enabled regardless of its level
-//! and any event with a field name ending with `_counter` or `_gauge`.
+//! and any event with a field name ending with `_counter`, `_gauge` or `_histogram`.
 //!
 //! # Example
 //!
@@ -53,8 +53,8 @@ pub struct MetricsSubscriber<S> {
 }
 
 /// A `tracing_core::field::Visit` implementation that captures fields
-/// that contain `counter` or `gague` in their name and dispatches the `i64`
-/// or `u64` value to the underlying metrics sink.
+/// that contain `counter`, `gague` or `histogram` in their name and dispatches
+/// the `i64` or `u64` value to the underlying metrics sink.
 pub struct MetricVisitor {
     collector: Collector,
 }
@@ -162,10 +162,7 @@ impl<S: Subscriber> Subscriber for MetricsSubscriber<S> {
     // extra non required fn
     fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
         if metadata.name().contains("event")
-            && metadata
-                .fields()
-                .iter()
-                .any(|f| f.name().ends_with("_counter") || f.name().ends_with("_gauge"))
+            && metadata.fields().iter().any(|f| is_metric(f.name()))
             && !metadata
                 .fields()
                 .iter()
@@ -232,6 +229,8 @@ impl Visit for MetricVisitor {
             self.collector.update_count(field.name(), value as i64);
         } else if field.name().ends_with("_gauge") {
             self.collector.update_gauge(field.name(), value);
+        } else if field.name().ends_with("_histogram") {
+            self.collector.update_value(field.name(), value);
         }
     }
 
@@ -240,6 +239,12 @@ impl Visit for MetricVisitor {
             self.collector.update_count(field.name(), value);
         } else if field.name().ends_with("_gauge") {
             self.collector.update_gauge(field.name(), value as u64);
+        } else if field.name().ends_with("_histogram") {
+            self.collector.update_value(field.name(), value as u64);
         }
     }
 }
+
+fn is_metric(name: &str) -> bool {
+    name.ends_with("_counter") || name.ends_with("_gauge") || name.ends_with("_histogram")
+}
@@ -320,7 +320,7 @@ class Links
         name = $1
         type = $2.pluralize
         source_file_url =
-          if ["statsd"].include?(name)
+          if ["elasticsearch", "statsd"].include?(name)
             "#{VECTOR_ROOT}/tree/master/src/#{type}/#{name}/mod.rs"
           else
             "#{VECTOR_ROOT}/tree/master/src/#{type}/#{name}.rs"
@@ -92,6 +92,15 @@ class Sink < Component
         "unit" => "bytes"
       }
 
+      buffer_options["max_age_secs"] = {
+        "description" => "Events older than this, going by their timestamp, are dropped instead of being sent when they're read from the buffer, so that data past its retention isn't replayed after a long outage. Dropped events are counted by the `stale_buffered_events_dropped_counter` internal metric.",
+        "examples" => [86400],
+        "null" => true,
+        "relevant_when" => {"type" => "disk"},
+        "type" => "int",
+        "unit" => "seconds"
+      }
+
       buffer_options["num_items"] = {
         "description" => "The maximum number of [events][docs.event] allowed in the buffer.",
         "default" => 500,
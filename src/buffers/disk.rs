@@ -1,6 +1,7 @@
 #![cfg(feature = "leveldb")]
 
-use crate::event::{proto, Event};
+use crate::event::{self, proto, Event, Metric, ValueKind};
+use chrono::{DateTime, Utc};
 use futures::{
     task::{self, AtomicTask, Task},
     Async, AsyncSink, Poll, Sink, Stream,
@@ -22,6 +23,7 @@ use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc, Mutex,
 };
+use std::time::Duration;
 
 #[derive(Copy, Clone, Debug)]
 struct Key(usize);
@@ -150,7 +152,11 @@ pub struct Reader {
     blocked_write_tasks: Arc<Mutex<Vec<Task>>>,
     current_size: Arc<AtomicUsize>,
     ack_counter: Arc<AtomicUsize>,
-    unacked_sizes: VecDeque<usize>,
+    // The size of every event read but not yet deleted, and whether it was
+    // skipped instead of being handed to the sink, as stale or undecodable
+    // events are, so the sink never acks it.
+    unacked: VecDeque<(usize, bool)>,
+    max_age: Option<Duration>,
 }
 
 // Writebatch isn't Send, but the leveldb docs explicitly say that it's okay to share across threads
@@ -167,35 +173,43 @@ impl Stream for Reader {
         // using write_notifier to wake this task up after the next write.
         self.write_notifier.register();
 
-        // This will usually complete instantly, but in the case of a large queue (or a fresh launch of
-        // the app), this will have to go to disk.
-        let next = tokio_threadpool::blocking(|| {
-            self.db
-                .get(ReadOptions::new(), Key(self.read_offset))
-                .unwrap()
-        })
-        .unwrap();
-
-        if let Async::Ready(Some(value)) = next {
-            self.unacked_sizes.push_back(value.len());
-            self.read_offset += 1;
+        loop {
+            // This will usually complete instantly, but in the case of a large queue (or a fresh launch of
+            // the app), this will have to go to disk.
+            let next = tokio_threadpool::blocking(|| {
+                self.db
+                    .get(ReadOptions::new(), Key(self.read_offset))
+                    .unwrap()
+            })
+            .unwrap();
 
-            match proto::EventWrapper::decode(value) {
-                Ok(event) => {
-                    let event = Event::from(event);
-                    Ok(Async::Ready(Some(event)))
-                }
-                Err(err) => {
-                    error!("Error deserializing proto: {:?}", err);
-                    debug_assert!(false);
-                    self.poll()
+            if let Async::Ready(Some(value)) = next {
+                let size = value.len();
+                self.read_offset += 1;
+
+                match proto::EventWrapper::decode(value) {
+                    Ok(event) => {
+                        let event = Event::from(event);
+                        if self.is_stale(&event) {
+                            trace!(stale_buffered_events_dropped_counter = 1);
+                            self.unacked.push_back((size, true));
+                            continue;
+                        }
+                        self.unacked.push_back((size, false));
+                        return Ok(Async::Ready(Some(event)));
+                    }
+                    Err(err) => {
+                        self.unacked.push_back((size, true));
+                        error!("Error deserializing proto: {:?}", err);
+                        debug_assert!(false);
+                    }
                 }
+            } else if Arc::strong_count(&self.db) == 1 {
+                // There are no writers left
+                return Ok(Async::Ready(None));
+            } else {
+                return Ok(Async::NotReady);
             }
-        } else if Arc::strong_count(&self.db) == 1 {
-            // There are no writers left
-            Ok(Async::Ready(None))
-        } else {
-            Ok(Async::NotReady)
         }
     }
 }
@@ -207,8 +221,38 @@ impl Drop for Reader {
 }
 
 impl Reader {
+    fn is_stale(&self, event: &Event) -> bool {
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return false,
+        };
+        match timestamp(event) {
+            Some(timestamp) => (Utc::now() - timestamp)
+                .to_std()
+                .map(|age| age > max_age)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
     fn delete_acked(&mut self) {
-        let num_to_delete = self.ack_counter.swap(0, Ordering::Relaxed);
+        let mut acks = self.ack_counter.swap(0, Ordering::Relaxed);
+
+        // Skipped events are deleted along with the acked events around them,
+        // so that everything from the first unacked event on is kept.
+        let mut num_to_delete = 0;
+        let mut size_deleted = 0;
+        while let Some(&(size, stale)) = self.unacked.front() {
+            if !stale {
+                if acks == 0 {
+                    break;
+                }
+                acks -= 1;
+            }
+            self.unacked.pop_front();
+            num_to_delete += 1;
+            size_deleted += size;
+        }
 
         if num_to_delete > 0 {
             let new_offset = self.delete_offset + num_to_delete;
@@ -229,7 +273,6 @@ impl Reader {
 
             self.db.compact(&Key(0), &Key(self.delete_offset));
 
-            let size_deleted = self.unacked_sizes.drain(..num_to_delete).sum();
             self.current_size.fetch_sub(size_deleted, Ordering::Relaxed);
         }
 
@@ -239,10 +282,24 @@ impl Reader {
     }
 }
 
+fn timestamp(event: &Event) -> Option<DateTime<Utc>> {
+    match event {
+        Event::Log(log) => log
+            .get(&event::TIMESTAMP)
+            .and_then(ValueKind::as_timestamp)
+            .cloned(),
+        Event::Metric(Metric::Counter { timestamp, .. })
+        | Event::Metric(Metric::Histogram { timestamp, .. })
+        | Event::Metric(Metric::Gauge { timestamp, .. })
+        | Event::Metric(Metric::Set { timestamp, .. }) => *timestamp,
+    }
+}
+
 pub fn open(
     data_dir: &Path,
     buffer_dir: &Path,
     max_size: usize,
+    max_age: Option<Duration>,
 ) -> Result<(Writer, Reader, super::Acker), String> {
     let path = data_dir.join(buffer_dir);
 
@@ -279,7 +336,11 @@ pub fn open(
     let blocked_write_tasks = Arc::new(Mutex::new(Vec::new()));
 
     let ack_counter = Arc::new(AtomicUsize::new(0));
-    let acker = super::Acker::Disk(Arc::clone(&ack_counter), Arc::clone(&write_notifier));
+    let acker = super::Acker::Disk(
+        Arc::clone(&ack_counter),
+        Arc::clone(&write_notifier),
+        Arc::new(AtomicUsize::new(0)),
+    );
 
     let writer = Writer {
         db: Arc::clone(&db),
@@ -299,7 +360,8 @@ pub fn open(
         delete_offset: head,
         current_size,
         ack_counter,
-        unacked_sizes: VecDeque::new(),
+        unacked: VecDeque::new(),
+        max_age,
     };
 
     Ok((writer, reader, acker))
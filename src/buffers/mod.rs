@@ -22,6 +22,9 @@ pub enum BufferConfig {
     Disk {
         max_size: usize,
         when_full: WhenFull,
+        /// Events older than this, by their timestamp, are dropped instead of
+        /// being sent when they're read from the buffer.
+        max_age_secs: Option<u64>,
     },
 }
 
@@ -108,13 +111,16 @@ impl BufferConfig {
             BufferConfig::Disk {
                 max_size,
                 when_full,
+                max_age_secs,
             } => {
                 let data_dir = data_dir
                     .as_ref()
                     .ok_or_else(|| "Must set data_dir to use on-disk buffering.".to_string())?;
                 let buffer_dir = format!("{}_buffer", sink_name);
 
-                let (tx, rx, acker) = disk::open(&data_dir, buffer_dir.as_ref(), *max_size)?;
+                let max_age = max_age_secs.map(std::time::Duration::from_secs);
+                let (tx, rx, acker) =
+                    disk::open(&data_dir, buffer_dir.as_ref(), *max_size, max_age)?;
                 let tx = BufferInputCloner::Disk(tx, *when_full);
                 let rx = Box::new(rx);
                 Ok((tx, rx, acker))
@@ -123,8 +129,11 @@ impl BufferConfig {
     }
 }
 
+// The disk variant holds the count of acked events not yet deleted from the
+// buffer, the task waiting for it, and the count of events sent but not acked.
+#[derive(Clone)]
 pub enum Acker {
-    Disk(Arc<AtomicUsize>, Arc<AtomicTask>),
+    Disk(Arc<AtomicUsize>, Arc<AtomicTask>, Arc<AtomicUsize>),
     Null,
 }
 
@@ -138,17 +147,54 @@ impl Acker {
     pub fn ack(&self, num: usize) {
         match self {
             Acker::Null => {}
-            Acker::Disk(counter, notifier) => {
+            Acker::Disk(counter, notifier, pending) => {
                 counter.fetch_add(num, Ordering::Relaxed);
                 notifier.notify();
+
+                // Not every sink reports what it sends.
+                let mut current = pending.load(Ordering::Relaxed);
+                loop {
+                    let remaining = current.saturating_sub(num);
+                    match pending.compare_exchange_weak(
+                        current,
+                        remaining,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => break,
+                        Err(actual) => current = actual,
+                    }
+                }
             }
         }
     }
 
+    // This method may be called by a sink when it starts sending the next `num`
+    // events, so that they're counted by `pending` until they're acked.
+    pub fn sent(&self, num: usize) {
+        if let Acker::Disk(_, _, pending) = self {
+            pending.fetch_add(num, Ordering::Relaxed);
+        }
+    }
+
+    // The number of events a sink has started sending but not acked yet, which
+    // grows while it falls behind. Memory buffers don't track acks, so this is
+    // always 0 for them.
+    pub fn pending(&self) -> usize {
+        match self {
+            Acker::Null => 0,
+            Acker::Disk(_, _, pending) => pending.load(Ordering::Relaxed),
+        }
+    }
+
     pub fn new_for_testing() -> (Self, Arc<AtomicUsize>) {
         let ack_counter = Arc::new(AtomicUsize::new(0));
         let notifier = Arc::new(AtomicTask::new());
-        let acker = Acker::Disk(Arc::clone(&ack_counter), Arc::clone(&notifier));
+        let acker = Acker::Disk(
+            Arc::clone(&ack_counter),
+            Arc::clone(&notifier),
+            Arc::new(AtomicUsize::new(0)),
+        );
 
         (acker, ack_counter)
     }
@@ -1,3 +1,4 @@
+use crate::sinks::util::state;
 use futures::Future;
 use hotmic::{
     snapshot::{Snapshot, TypedMeasurement},
@@ -31,8 +32,14 @@ pub fn serve(addr: &SocketAddr, controller: Controller) -> impl Future<Item = ()
         let connection_span = info_span!("connection", addr = field::display(&connection_addr));
         let controller = controller.clone();
 
-        service_fn_ok(move |_: Request<Body>| {
+        service_fn_ok(move |request: Request<Body>| {
             connection_span.in_scope(|| {
+                if request.uri().path() == "/sinks" {
+                    debug!(message = "serializing sink state.");
+                    let output = serde_json::to_string(&state::snapshot()).unwrap();
+                    return Response::new(Body::from(output));
+                }
+
                 debug!(message = "snapshotting metrics.");
                 let snapshot = controller.get_snapshot().unwrap();
                 let output = process_snapshot(snapshot).unwrap();
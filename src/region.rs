@@ -26,25 +26,67 @@ impl RegionOrEndpoint {
     }
 }
 
+/// When both are set, the custom endpoint is where requests are sent and the
+/// region is only used to sign them.
 impl TryFrom<RegionOrEndpoint> for Region {
     type Error = String;
 
     fn try_from(r: RegionOrEndpoint) -> Result<Self, Self::Error> {
         match (r.region, r.endpoint) {
             (Some(region), None) => region.parse().map_err(|e| format!("{}", e)),
-            (None, Some(endpoint)) => endpoint
-                .parse::<Uri>()
-                .map(|_| Region::Custom {
+            (None, Some(endpoint)) => {
+                parse_endpoint(&endpoint)?;
+                Ok(Region::Custom {
                     name: "custom".into(),
                     endpoint,
                 })
-                .map_err(|e| format!("Failed to parse custom endpoint as URI: {}", e)),
-            (Some(_), Some(_)) => Err("Only one of 'region' or 'endpoint' can be specified".into()),
+            }
+            (Some(region), Some(endpoint)) => {
+                if region.trim().is_empty() {
+                    return Err("'region' must not be empty when set with 'endpoint'".into());
+                }
+                let uri = parse_endpoint(&endpoint)?;
+                if let Some(endpoint_region) = aws_endpoint_region(&uri) {
+                    if endpoint_region != region {
+                        warn!(
+                            message = "Custom endpoint is in a different region than 'region'; requests are sent to the endpoint and signed for 'region'.",
+                            %endpoint,
+                            %region
+                        );
+                    }
+                }
+                Ok(Region::Custom {
+                    name: region,
+                    endpoint,
+                })
+            }
             (None, None) => Err("Must set 'region' or 'endpoint'".into()),
         }
     }
 }
 
+fn parse_endpoint(endpoint: &str) -> Result<Uri, String> {
+    let uri = endpoint
+        .parse::<Uri>()
+        .map_err(|e| format!("Failed to parse custom endpoint as URI: {}", e))?;
+    if uri.host().is_none() {
+        return Err(format!("Custom endpoint {:?} has no host", endpoint));
+    }
+    Ok(uri)
+}
+
+// The region named in an AWS endpoint such as `s3.eu-west-1.amazonaws.com`.
+fn aws_endpoint_region(uri: &Uri) -> Option<String> {
+    let host = uri.host()?;
+    if !host.ends_with(".amazonaws.com") {
+        return None;
+    }
+    host.split('.')
+        .filter_map(|label| label.parse::<Region>().ok())
+        .map(|region| region.name().to_string())
+        .next()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,6 +138,74 @@ mod tests {
         assert_eq!(region, expected_region);
     }
 
+    #[test]
+    fn region_with_custom_endpoint_signs_for_region() {
+        let config: Config = toml::from_str(
+            r#"
+        [inner]
+        region = "eu-west-1"
+        endpoint = "http://localhost:9000"
+        "#,
+        )
+        .unwrap();
+
+        let expected_region = Region::Custom {
+            name: "eu-west-1".into(),
+            endpoint: "http://localhost:9000".into(),
+        };
+
+        let region: Region = config.inner.region.try_into().unwrap();
+        assert_eq!(region, expected_region);
+    }
+
+    #[test]
+    fn region_conflicting_aws_endpoint_sends_to_endpoint() {
+        let region: Region = RegionOrEndpoint {
+            region: Some("us-east-1".into()),
+            endpoint: Some("https://s3.eu-west-1.amazonaws.com".into()),
+        }
+        .try_into()
+        .unwrap();
+
+        let expected_region = Region::Custom {
+            name: "us-east-1".into(),
+            endpoint: "https://s3.eu-west-1.amazonaws.com".into(),
+        };
+        assert_eq!(region, expected_region);
+        assert_eq!(
+            Some("eu-west-1".to_string()),
+            aws_endpoint_region(&"https://s3.eu-west-1.amazonaws.com".parse().unwrap())
+        );
+        assert_eq!(
+            None,
+            aws_endpoint_region(&"http://minio.eu-west-1.local".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn region_invalid_combinations() {
+        let error = |region: Option<&str>, endpoint: Option<&str>| {
+            let region: Result<Region, String> = RegionOrEndpoint {
+                region: region.map(Into::into),
+                endpoint: endpoint.map(Into::into),
+            }
+            .try_into();
+            region.unwrap_err()
+        };
+
+        assert_eq!(
+            "'region' must not be empty when set with 'endpoint'",
+            error(Some(""), Some("http://localhost:9000"))
+        );
+        assert_eq!(
+            "Custom endpoint \"/path\" has no host",
+            error(Some("us-east-1"), Some("/path"))
+        );
+        assert!(error(Some("us-east-1"), Some("http://bad host"))
+            .starts_with("Failed to parse custom endpoint as URI"));
+        assert!(!error(Some("nowhere-1"), None).is_empty());
+    }
+
     #[test]
     fn region_not_provided() {
         let config: Config = toml::from_str(
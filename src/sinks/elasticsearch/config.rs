@@ -0,0 +1,898 @@
+use super::{retry::retry_ambiguous_failures, service::DEFAULT_COALESCE_COUNT_FIELD};
+use crate::{
+    event::{Event, LogEvent},
+    sinks::{
+        tcp::{load_key, load_x509},
+        util::{http::HostTls, Compression, PostShutdownPolicy},
+    },
+    topology::config::SinkConfig,
+};
+use http::header::HeaderValue;
+use openssl::pkcs12::Pkcs12;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ElasticSearchConfig {
+    pub host: String,
+    pub mode: Option<ElasticSearchMode>,
+    pub bulk_action: Option<BulkAction>,
+    pub index: Option<String>,
+    pub index_fallback: Option<String>,
+    pub timezone: Option<String>,
+    pub metrics_as_logs: Option<bool>,
+    pub metrics_index: Option<String>,
+    pub level_routing: Option<HashMap<String, String>>,
+    pub level_key: Option<String>,
+    pub warm_index: Option<String>,
+    pub warm_after_secs: Option<u64>,
+    pub pipeline_key: Option<String>,
+    pub pipeline_map: Option<HashMap<String, String>>,
+    pub default_pipeline: Option<String>,
+    pub pipeline: Option<String>,
+    pub raw_action_key: Option<String>,
+    pub doc_type: Option<String>,
+    pub api_version: Option<ApiVersion>,
+    pub custom_doc_type: Option<CustomDocTypePolicy>,
+    pub id_key: Option<IdKey>,
+    pub id_key_separator: Option<String>,
+    pub id_strategy: Option<IdStrategy>,
+    pub remove_id_field: Option<bool>,
+    pub id_prefix: Option<String>,
+    pub id_suffix: Option<String>,
+    pub non_string_id: Option<NonStringIdPolicy>,
+    pub version_key: Option<String>,
+    pub version_type: Option<VersionType>,
+    pub version_type_key: Option<String>,
+    pub batch_size: Option<usize>,
+    pub batch_timeout: Option<u64>,
+    pub aligned_flush: Option<bool>,
+    pub min_batch_events: Option<usize>,
+    pub max_batch_age_secs: Option<u64>,
+    pub coalesce_identical: Option<bool>,
+    pub coalesce_keys: Option<Vec<String>>,
+    pub coalesce_count_field: Option<String>,
+    pub compression: Option<Compression>,
+    pub compression_threshold_bytes: Option<usize>,
+    pub compression_level: Option<u32>,
+    pub encoding: Option<ElasticSearchEncoding>,
+
+    // Tower Request based configuration
+    pub request_in_flight_limit: Option<InFlightLimit>,
+    pub request_timeout_secs: Option<u64>,
+    pub request_rate_limit_duration_secs: Option<u64>,
+    pub request_rate_limit_num: Option<u64>,
+    pub request_retry_attempts: Option<usize>,
+    pub request_retry_backoff_secs: Option<u64>,
+    pub request_retry_max_backoff_secs: Option<u64>,
+    pub request_retry_jitter: Option<bool>,
+    pub request_retry_in_flight_fraction: Option<f64>,
+    pub request_retry_invalid_response_attempts: Option<usize>,
+    pub request_retry_ambiguous_failures: Option<bool>,
+    pub request_retry_rejected_execution_backoff_secs: Option<u64>,
+    pub index_creation_rate_limit_num: Option<usize>,
+    pub index_creation_rate_limit_duration_secs: Option<u64>,
+    pub detailed_timing: Option<bool>,
+    pub follow_redirects: Option<bool>,
+    pub dns_last_good_ttl_secs: Option<u64>,
+
+    pub basic_auth: Option<ElasticSearchBasicAuthConfig>,
+    pub auth: Option<ElasticSearchAuth>,
+
+    pub headers: Option<HashMap<String, String>>,
+    pub query: Option<HashMap<String, String>>,
+    pub tenant_key: Option<String>,
+    pub tenant_query_param: Option<String>,
+
+    pub shadow_index: Option<String>,
+    #[serde(default)]
+    pub index_denylist: Vec<String>,
+    pub flush_on_field: Option<FlushOnFieldConfig>,
+    pub flush_on_distinct_keys: Option<FlushOnDistinctKeysConfig>,
+    pub normalize_field_names: Option<NormalizeFieldNamesConfig>,
+    pub max_fields_per_document: Option<usize>,
+    pub extra_fields: Option<ExtraFieldsPolicy>,
+    pub numeric_types: Option<HashMap<String, NumericType>>,
+    pub field_types: Option<HashMap<String, TypeHint>>,
+    pub field_type_violations: Option<SchemaViolationPolicy>,
+    pub truncate_fields: Option<HashMap<String, usize>>,
+    pub default_fields: Option<HashMap<String, String>>,
+    pub default_fields_precedence: Option<DefaultFieldsPrecedence>,
+    pub metadata_fields: Option<HashMap<String, MetadataField>>,
+    pub future_timestamps: Option<FutureTimestampPolicy>,
+    pub future_timestamp_tolerance_secs: Option<u64>,
+    pub flattened_fields: Option<Vec<String>>,
+    pub auto_create_index: Option<bool>,
+    pub ignore_version_conflicts: Option<bool>,
+    pub coerce_on_mapping_error: Option<bool>,
+    pub emit_internal_events: Option<bool>,
+    pub failed_document_sample_bytes: Option<usize>,
+    pub failed_document_redact_fields: Option<Vec<String>>,
+    pub auto_create_index_mapping: Option<PathBuf>,
+    pub max_array_depth: Option<usize>,
+    pub max_line_bytes: Option<usize>,
+    pub oversized_lines: Option<OversizedLinePolicy>,
+    pub array_nesting: Option<ArrayNestingPolicy>,
+    pub schema: Option<PathBuf>,
+    pub schema_violations: Option<SchemaViolationPolicy>,
+    pub add_sequence_field: Option<String>,
+    pub sequence_state_path: Option<PathBuf>,
+
+    pub dump_failed_batches_to: Option<PathBuf>,
+    pub dump_failed_batches_max_files: Option<usize>,
+    pub dlq_sink: Option<DeadLetterSinkConfig>,
+
+    pub tls: Option<ElasticSearchTlsConfig>,
+    pub tls_hosts: Option<HashMap<String, ElasticSearchHostTlsConfig>>,
+
+    pub post_shutdown_events: Option<PostShutdownPolicy>,
+    pub log_resolved_config: Option<bool>,
+    pub log_capabilities: Option<bool>,
+    pub request_id_header: Option<String>,
+    pub detailed_metrics: Option<bool>,
+    pub debug_tee_rate: Option<f64>,
+    pub debug_tee_redact_fields: Option<Vec<String>>,
+    pub healthcheck_level: Option<HealthcheckLevel>,
+    pub healthcheck_timeout_secs: Option<u64>,
+    pub healthcheck_min_status: Option<HealthStatus>,
+    pub shared_batch: Option<String>,
+}
+
+/// Any sink's config, with its `type`, for the sink that documents of failed
+/// batches are forwarded to. Sink configs can't be cloned on their own, so
+/// clones share it.
+#[derive(Debug, Clone)]
+pub struct DeadLetterSinkConfig(pub Arc<Box<dyn SinkConfig>>);
+
+impl Serialize for DeadLetterSinkConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.as_ref().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DeadLetterSinkConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        <Box<dyn SinkConfig>>::deserialize(deserializer).map(|config| Self(Arc::new(config)))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ElasticSearchBasicAuthConfig {
+    pub password: String,
+    pub user: String,
+}
+
+impl ElasticSearchBasicAuthConfig {
+    /// The value of the `Authorization` header for these credentials.
+    pub(super) fn authorization(&self) -> String {
+        let token = format!("{}:{}", self.user, self.password);
+        format!("Basic {}", base64::encode(token.as_bytes()))
+    }
+}
+
+/// How requests authenticate with the cluster. `basic` is the same as
+/// `basic_auth`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ElasticSearchAuth {
+    Basic { user: String, password: String },
+    ApiKey { id: String, api_key: String },
+    Bearer { token: String },
+}
+
+impl ElasticSearchAuth {
+    /// The value of the `Authorization` header for these credentials.
+    pub(super) fn authorization(&self) -> String {
+        match self {
+            ElasticSearchAuth::Basic { user, password } => ElasticSearchBasicAuthConfig {
+                user: user.clone(),
+                password: password.clone(),
+            }
+            .authorization(),
+            ElasticSearchAuth::ApiKey { id, api_key } => {
+                let token = format!("{}:{}", id, api_key);
+                format!("ApiKey {}", base64::encode(token.as_bytes()))
+            }
+            ElasticSearchAuth::Bearer { token } => format!("Bearer {}", token),
+        }
+    }
+}
+
+/// The `Authorization` header requests are sent with, if any.
+pub(super) fn authorization(config: &ElasticSearchConfig) -> Result<Option<String>, String> {
+    let authorization = match (&config.auth, &config.basic_auth) {
+        (Some(_), Some(_)) => return Err("auth and basic_auth can't both be set".into()),
+        (Some(auth), None) => auth.authorization(),
+        (None, Some(auth)) => auth.authorization(),
+        (None, None) => return Ok(None),
+    };
+    HeaderValue::from_str(&authorization)
+        .map_err(|_| "Invalid auth credentials: they can't be sent in a header".to_string())?;
+    Ok(Some(authorization))
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ElasticSearchTlsConfig {
+    pub verify_certificate: Option<bool>,
+    pub sni: Option<String>,
+    pub crt_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub ca_path: Option<PathBuf>,
+}
+
+impl ElasticSearchTlsConfig {
+    /// The client certificate and key, for mutual TLS.
+    pub(super) fn identity(&self) -> Result<Option<native_tls::Identity>, String> {
+        let (crt_path, key_path) = match (&self.crt_path, &self.key_path) {
+            (Some(crt_path), Some(key_path)) => (crt_path, key_path),
+            (None, None) => return Ok(None),
+            _ => return Err("tls.crt_path and tls.key_path must be set together".into()),
+        };
+
+        let crt = load_x509(crt_path)?;
+        let key = load_key(key_path, &None)?;
+        let der = Pkcs12::builder()
+            .build("", "", &key, &crt)
+            .and_then(|identity| identity.to_der())
+            .map_err(|err| format!("Could not build PKCS#12 archive for identity: {}", err))?;
+        native_tls::Identity::from_pkcs12(&der, "")
+            .map(Some)
+            .map_err(|err| format!("Could not load identity {:?}: {}", crt_path, err))
+    }
+}
+
+/// TLS trust overrides for a single host, e.g. a development cluster with a
+/// self-signed certificate.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ElasticSearchHostTlsConfig {
+    pub verify_certificate: Option<bool>,
+    pub ca_path: Option<PathBuf>,
+}
+
+impl ElasticSearchHostTlsConfig {
+    pub(super) fn build(&self) -> Result<HostTls, String> {
+        let ca = match &self.ca_path {
+            Some(path) => {
+                let pem = std::fs::read(path)
+                    .map_err(|err| format!("Could not read CA file {:?}: {}", path, err))?;
+                let ca = native_tls::Certificate::from_pem(&pem)
+                    .map_err(|err| format!("Could not parse CA file {:?}: {}", path, err))?;
+                Some(ca)
+            }
+            None => None,
+        };
+
+        Ok(HostTls {
+            verify_certificate: self.verify_certificate.unwrap_or(true),
+            ca,
+        })
+    }
+}
+
+/// Flush the current batch right after an event whose `field` equals `value`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FlushOnFieldConfig {
+    pub field: String,
+    pub value: String,
+}
+
+impl FlushOnFieldConfig {
+    pub(super) fn matches(&self, event: &Event) -> bool {
+        let log = match event {
+            Event::Log(log) => log,
+            Event::Metric(_) => return false,
+        };
+        log.get(&self.field.as_str().into())
+            .map(|value| value.as_bytes() == self.value.as_bytes())
+            .unwrap_or(false)
+    }
+}
+
+/// Flush the current batch once its events span `threshold` distinct values
+/// of `field`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FlushOnDistinctKeysConfig {
+    pub field: String,
+    pub threshold: usize,
+}
+
+impl FlushOnDistinctKeysConfig {
+    pub(super) fn key(&self, event: &Event) -> Option<String> {
+        match event {
+            Event::Log(log) => log
+                .get(&self.field.as_str().into())
+                .map(|value| value.to_string_lossy()),
+            Event::Metric(_) => None,
+        }
+    }
+}
+
+/// Rewrites field names into ones Elasticsearch accepts: characters other
+/// than alphanumerics, `_`, `-` and `@` are replaced in every path segment and
+/// leading underscores, reserved for metadata fields, are stripped.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizeFieldNamesConfig {
+    pub replacement: Option<char>,
+    pub strip_leading_underscores: Option<bool>,
+    pub on_collision: Option<FieldNameCollisionPolicy>,
+}
+
+/// What to do when a normalized field name is already taken.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldNameCollisionPolicy {
+    Suffix,
+    DropField,
+    DropEvent,
+}
+
+impl NormalizeFieldNamesConfig {
+    fn normalize_name(&self, name: &str) -> String {
+        let replacement = self.replacement.unwrap_or('_');
+        let normalized = name
+            .split('.')
+            .map(|segment| {
+                segment
+                    .chars()
+                    .map(|c| match c {
+                        c if c.is_alphanumeric() => c,
+                        '_' | '-' | '@' => c,
+                        _ => replacement,
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join(".");
+
+        if self.strip_leading_underscores.unwrap_or(true) {
+            let stripped = normalized.trim_start_matches('_');
+            if !stripped.is_empty() {
+                return stripped.to_string();
+            }
+        }
+        normalized
+    }
+
+    pub(super) fn normalize(&self, mut log: LogEvent) -> Option<LogEvent> {
+        let mut renames = log
+            .keys()
+            .map(|key| (key.to_string(), self.normalize_name(key)))
+            .filter(|(key, normalized)| key != normalized)
+            .collect::<Vec<_>>();
+        // Sorted so that collisions resolve the same way for every event.
+        renames.sort();
+
+        let mut values = Vec::with_capacity(renames.len());
+        for (key, _) in &renames {
+            values.push(log.remove(&key.as_str().into()).unwrap());
+        }
+
+        for ((key, normalized), value) in renames.into_iter().zip(values) {
+            let mut name = normalized.into();
+            if log.get(&name).is_some() {
+                match self
+                    .on_collision
+                    .unwrap_or(FieldNameCollisionPolicy::Suffix)
+                {
+                    FieldNameCollisionPolicy::Suffix => {
+                        name = (1..)
+                            .map(|n| format!("{}_{}", name, n).into())
+                            .find(|candidate| log.get(candidate).is_none())
+                            .unwrap();
+                    }
+                    FieldNameCollisionPolicy::DropField => {
+                        warn!(
+                            message = "normalized field name is already taken. Dropping field.",
+                            field = %key,
+                            field_name_collisions_counter = 1
+                        );
+                        continue;
+                    }
+                    FieldNameCollisionPolicy::DropEvent => {
+                        warn!(
+                            message = "normalized field name is already taken. Dropping event.",
+                            field = %key,
+                            field_name_collisions_counter = 1
+                        );
+                        return None;
+                    }
+                }
+            }
+            log.insert_explicit(name, value);
+        }
+
+        Some(log)
+    }
+}
+
+/// What to do with the fields of a document beyond `max_fields_per_document`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraFieldsPolicy {
+    DropExtra,
+    Error,
+    NestExtraUnder(String),
+}
+
+/// What to do with events timestamped further in the future than
+/// `future_timestamp_tolerance_secs`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FutureTimestampPolicy {
+    Keep,
+    Clamp,
+    Drop,
+}
+
+/// What to do with documents whose line is longer than `max_line_bytes`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedLinePolicy {
+    Drop,
+    Truncate,
+}
+
+/// What to do with arrays nested deeper than `max_array_depth`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrayNestingPolicy {
+    Flatten,
+    Stringify,
+}
+
+/// Which side wins when a default field overlaps a field of the event.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultFieldsPrecedence {
+    Event,
+    Defaults,
+}
+
+/// What Vector knows about an event that isn't one of its fields.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataField {
+    /// `log` or `metric`.
+    EventType,
+    /// The host Vector runs on.
+    VectorHost,
+    /// The version of Vector that sent the document.
+    VectorVersion,
+}
+
+/// The JSON number type a field is always sent as, so that dynamic mapping
+/// sees the same type no matter which document it infers it from.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NumericType {
+    Float,
+    Integer,
+}
+
+/// The type a field is checked against, and coerced to when it can be, so
+/// that values that drifted don't conflict with the field's mapping.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TypeHint {
+    Integer,
+    Float,
+    Keyword,
+    Boolean,
+}
+
+/// What to do with documents that don't conform to `schema`, or with values
+/// that can't be coerced to their `field_types`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaViolationPolicy {
+    Drop,
+    Dlq,
+    PassWithWarning,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ElasticSearchEncoding {
+    pub codec: Option<Codec>,
+}
+
+/// The format documents are serialized in.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Json,
+    Cbor,
+    Msgpack,
+}
+
+impl Codec {
+    pub(super) fn content_type(self) -> &'static str {
+        match self {
+            Codec::Json => "application/x-ndjson",
+            Codec::Cbor => "application/cbor",
+            Codec::Msgpack => "application/msgpack",
+        }
+    }
+
+    // The bulk API splits its body into lines, which only works for
+    // newline delimited JSON (and SMILE); binary formats are refused.
+    pub(super) fn check_bulk_support(self) -> Result<(), String> {
+        match self {
+            Codec::Json => Ok(()),
+            Codec::Cbor | Codec::Msgpack => Err(format!(
+                "Elasticsearch's bulk API does not accept {} bodies; use the json codec",
+                self.content_type()
+            )),
+        }
+    }
+}
+
+/// The major version of the target cluster.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiVersion {
+    V6,
+    V7,
+}
+
+/// What to do with a `doc_type` other than `_doc` on a typeless (7.x) cluster.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomDocTypePolicy {
+    Warn,
+    Coerce,
+}
+
+/// Whether documents are indexed into regular indices or appended to data
+/// streams.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ElasticSearchMode {
+    Bulk,
+    DataStream,
+}
+
+/// The bulk action documents are written with.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    Index,
+    Create,
+    Update,
+}
+
+impl BulkAction {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            BulkAction::Index => "index",
+            BulkAction::Create => "create",
+            BulkAction::Update => "update",
+        }
+    }
+}
+
+/// How many requests may be in flight at once, either a fixed number or
+/// `"auto"` to scale with the number of CPUs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InFlightLimit {
+    Fixed(usize),
+    Auto,
+}
+
+impl InFlightLimit {
+    pub(super) fn resolve(self) -> usize {
+        match self {
+            InFlightLimit::Fixed(limit) => limit,
+            InFlightLimit::Auto => auto_in_flight_limit(num_cpus::get()),
+        }
+    }
+}
+
+// Requests mostly wait on the network, so a couple per CPU keeps them busy
+// without piling onto the cluster from large machines.
+pub(super) fn auto_in_flight_limit(cpus: usize) -> usize {
+    (cpus * 2).max(1).min(64)
+}
+
+impl<'de> Deserialize<'de> for InFlightLimit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(InFlightLimitVisitor)
+    }
+}
+
+pub(super) struct InFlightLimitVisitor;
+
+impl<'de> de::Visitor<'de> for InFlightLimitVisitor {
+    type Value = InFlightLimit;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a positive integer or \"auto\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, limit: u64) -> Result<Self::Value, E> {
+        if limit == 0 {
+            return Err(E::invalid_value(de::Unexpected::Unsigned(limit), &self));
+        }
+        Ok(InFlightLimit::Fixed(limit as usize))
+    }
+
+    fn visit_i64<E: de::Error>(self, limit: i64) -> Result<Self::Value, E> {
+        if limit <= 0 {
+            return Err(E::invalid_value(de::Unexpected::Signed(limit), &self));
+        }
+        self.visit_u64(limit as u64)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+        match s {
+            "auto" => Ok(InFlightLimit::Auto),
+            _ => Err(E::invalid_value(de::Unexpected::Str(s), &self)),
+        }
+    }
+}
+
+impl Serialize for InFlightLimit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            InFlightLimit::Fixed(limit) => serializer.serialize_u64(*limit as u64),
+            InFlightLimit::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+/// How much detail the healthcheck asks the cluster health API for.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthcheckLevel {
+    Cluster,
+    Indices,
+    Shards,
+}
+
+impl HealthcheckLevel {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            HealthcheckLevel::Cluster => "cluster",
+            HealthcheckLevel::Indices => "indices",
+            HealthcheckLevel::Shards => "shards",
+        }
+    }
+}
+
+/// The health the cluster reports, from worst to best.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Red,
+    Yellow,
+    Green,
+}
+
+impl HealthStatus {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            HealthStatus::Red => "red",
+            HealthStatus::Yellow => "yellow",
+            HealthStatus::Green => "green",
+        }
+    }
+}
+
+/// How Elasticsearch compares the version of a document with the version
+/// already indexed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionType {
+    Internal,
+    External,
+    ExternalGte,
+}
+
+impl VersionType {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            VersionType::Internal => "internal",
+            VersionType::External => "external",
+            VersionType::ExternalGte => "external_gte",
+        }
+    }
+
+    pub(super) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "internal" => Some(VersionType::Internal),
+            "external" => Some(VersionType::External),
+            "external_gte" => Some(VersionType::ExternalGte),
+            _ => None,
+        }
+    }
+}
+
+/// The field holding the document id, or several fields whose values are
+/// joined into it.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum IdKey {
+    Field(String),
+    Composite(Vec<String>),
+}
+
+impl IdKey {
+    pub(super) fn fields(&self) -> &[String] {
+        match self {
+            IdKey::Field(field) => std::slice::from_ref(field),
+            IdKey::Composite(fields) => fields,
+        }
+    }
+}
+
+impl From<&str> for IdKey {
+    fn from(field: &str) -> Self {
+        IdKey::Field(field.into())
+    }
+}
+
+/// How documents get their `_id`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// From the `id_key` fields, if any.
+    Key,
+    /// From a hash of the event, unless `id_key` is set.
+    Hash,
+}
+
+/// What to do when the `id_key` field holds a non-string value.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NonStringIdPolicy {
+    Coerce,
+    Skip,
+    Error,
+}
+
+impl ElasticSearchConfig {
+    /// This config with the defaults of the most commonly tuned options
+    /// filled in.
+    pub(super) fn resolved(&self) -> Self {
+        let mut resolved = self.clone();
+        resolved.mode = resolved.mode.or(Some(ElasticSearchMode::Bulk));
+        resolved.bulk_action = resolved.bulk_action.or(Some(BulkAction::Index));
+        resolved.index = resolved.index.or_else(|| Some("vector-%Y.%m.%d".into()));
+        resolved.timezone = resolved.timezone.or_else(|| Some("UTC".into()));
+        resolved.doc_type = resolved.doc_type.or_else(|| Some("_doc".into()));
+        resolved.non_string_id = resolved.non_string_id.or(Some(NonStringIdPolicy::Coerce));
+        if resolved.id_key.is_some() {
+            resolved.remove_id_field = resolved.remove_id_field.or(Some(false));
+        }
+        resolved.id_strategy = resolved.id_strategy.or(Some(IdStrategy::Key));
+        if let Some(IdKey::Composite(_)) = resolved.id_key {
+            resolved.id_key_separator = resolved.id_key_separator.or_else(|| Some(":".into()));
+        }
+        resolved.batch_size = resolved
+            .batch_size
+            .or_else(|| Some(bytesize::mib(10u64) as usize));
+        resolved.batch_timeout = resolved.batch_timeout.or(Some(1));
+        resolved.aligned_flush = resolved.aligned_flush.or(Some(false));
+        resolved.compression = resolved.compression.or(Some(Compression::Gzip));
+        resolved.encoding = Some(ElasticSearchEncoding {
+            codec: Some(
+                resolved
+                    .encoding
+                    .and_then(|encoding| encoding.codec)
+                    .unwrap_or(Codec::Json),
+            ),
+        });
+        resolved.request_in_flight_limit = Some(InFlightLimit::Fixed(
+            resolved
+                .request_in_flight_limit
+                .map_or(5, InFlightLimit::resolve),
+        ));
+        resolved.request_timeout_secs = resolved.request_timeout_secs.or(Some(60));
+        resolved.request_rate_limit_duration_secs =
+            resolved.request_rate_limit_duration_secs.or(Some(1));
+        resolved.request_rate_limit_num = resolved.request_rate_limit_num.or(Some(5));
+        resolved.request_retry_attempts =
+            resolved.request_retry_attempts.or(Some(usize::max_value()));
+        resolved.request_retry_backoff_secs = resolved.request_retry_backoff_secs.or(Some(1));
+        resolved.request_retry_max_backoff_secs =
+            resolved.request_retry_max_backoff_secs.or(Some(30));
+        resolved.request_retry_jitter = resolved.request_retry_jitter.or(Some(false));
+        resolved.request_retry_in_flight_fraction =
+            resolved.request_retry_in_flight_fraction.or(Some(1.0));
+        resolved.request_retry_invalid_response_attempts =
+            resolved.request_retry_invalid_response_attempts.or(Some(3));
+        resolved.request_retry_rejected_execution_backoff_secs = resolved
+            .request_retry_rejected_execution_backoff_secs
+            .or(resolved.request_retry_max_backoff_secs);
+        resolved.request_retry_ambiguous_failures = Some(retry_ambiguous_failures(self));
+        resolved.detailed_timing = resolved.detailed_timing.or(Some(false));
+        resolved.follow_redirects = resolved.follow_redirects.or(Some(false));
+        resolved.auto_create_index = resolved.auto_create_index.or(Some(false));
+        resolved.ignore_version_conflicts = resolved.ignore_version_conflicts.or(Some(false));
+        resolved.coerce_on_mapping_error = resolved.coerce_on_mapping_error.or(Some(false));
+        resolved.emit_internal_events = resolved.emit_internal_events.or(Some(false));
+        resolved.healthcheck_level = resolved
+            .healthcheck_level
+            .or(Some(HealthcheckLevel::Cluster));
+        resolved.healthcheck_timeout_secs = resolved.healthcheck_timeout_secs.or(Some(10));
+        resolved.healthcheck_min_status = resolved
+            .healthcheck_min_status
+            .or(Some(HealthStatus::Yellow));
+        resolved.tenant_query_param = resolved
+            .tenant_query_param
+            .or_else(|| Some("tenant".into()));
+        resolved.coalesce_identical = resolved.coalesce_identical.or(Some(false));
+        resolved.default_fields_precedence = resolved
+            .default_fields_precedence
+            .or(Some(DefaultFieldsPrecedence::Event));
+        resolved.debug_tee_rate = resolved.debug_tee_rate.or(Some(0.0));
+        resolved.detailed_metrics = resolved.detailed_metrics.or(Some(false));
+        resolved.future_timestamps = resolved
+            .future_timestamps
+            .or(Some(FutureTimestampPolicy::Keep));
+        if resolved.future_timestamps != Some(FutureTimestampPolicy::Keep) {
+            resolved.future_timestamp_tolerance_secs =
+                resolved.future_timestamp_tolerance_secs.or(Some(60));
+        }
+        if resolved.coalesce_identical == Some(true) {
+            resolved.coalesce_count_field = resolved
+                .coalesce_count_field
+                .or_else(|| Some(DEFAULT_COALESCE_COUNT_FIELD.into()));
+        }
+        if resolved.version_key.is_some() {
+            resolved.version_type = resolved.version_type.or(Some(VersionType::External));
+        }
+        if resolved.level_routing.is_some() {
+            resolved.level_key = resolved.level_key.or_else(|| Some("level".into()));
+        }
+        resolved
+    }
+
+    /// This config with its secrets replaced, for logging.
+    pub(super) fn redacted(mut self) -> Self {
+        const REDACTED: &str = "**REDACTED**";
+
+        if let Some(auth) = &mut self.basic_auth {
+            auth.password = REDACTED.into();
+        }
+        match &mut self.auth {
+            Some(ElasticSearchAuth::Basic {
+                password: secret, ..
+            })
+            | Some(ElasticSearchAuth::ApiKey {
+                api_key: secret, ..
+            })
+            | Some(ElasticSearchAuth::Bearer { token: secret }) => *secret = REDACTED.into(),
+            None => {}
+        }
+        for (name, value) in self.headers.iter_mut().flatten() {
+            let name = name.to_lowercase();
+            if name.contains("auth") || name.contains("token") || name.contains("key") {
+                *value = REDACTED.into();
+            }
+        }
+        self
+    }
+}
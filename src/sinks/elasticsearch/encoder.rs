@@ -0,0 +1,1601 @@
+use super::{
+    config::{
+        ApiVersion, ArrayNestingPolicy, BulkAction, CustomDocTypePolicy, DefaultFieldsPrecedence,
+        ElasticSearchConfig, ElasticSearchMode, ExtraFieldsPolicy, FutureTimestampPolicy, IdKey,
+        IdStrategy, MetadataField, NonStringIdPolicy, NormalizeFieldNamesConfig, NumericType,
+        OversizedLinePolicy, SchemaViolationPolicy, TypeHint, VersionType,
+    },
+    counter,
+    service::DeadLetters,
+};
+use crate::{
+    event::{self, Event, LogEvent, Metric, ValueKind},
+    sinks::util::{json_schema::JsonSchema, Compression},
+    sources::internal_events,
+    template::Template,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use flate2::write::GzEncoder;
+use lazy_static::lazy_static;
+use rand::{rngs::SmallRng, Rng};
+use regex::Regex;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use string_cache::DefaultAtom as Atom;
+
+/// Samples encoded documents to log for debugging, with sensitive fields
+/// redacted.
+pub(super) struct DebugTee {
+    pub(super) rate: f64,
+    // JSON pointers to the redacted fields.
+    pub(super) redact: Vec<String>,
+    rng: Mutex<SmallRng>,
+}
+
+impl DebugTee {
+    pub(super) fn new(rate: f64, redact: Vec<String>, rng: SmallRng) -> Self {
+        Self {
+            rate,
+            redact: redaction_pointers(&redact),
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// The line to print for an encoded action and document, if it's sampled.
+    pub(super) fn sample(&self, body: &[u8]) -> Option<String> {
+        if !self.rng.lock().unwrap().gen_bool(self.rate) {
+            return None;
+        }
+
+        let mut lines = body.splitn(2, |b| *b == b'\n');
+        let action = String::from_utf8_lossy(lines.next().unwrap_or_default());
+        let document = lines.next().unwrap_or_default();
+        let document = match serde_json::from_slice::<serde_json::Value>(document) {
+            Ok(mut document) => {
+                redact(&mut document, &self.redact);
+                document.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(document).trim_end().to_string(),
+        };
+        Some(format!("{} {}", action, document))
+    }
+}
+
+/// The JSON pointers to dotted field names.
+pub(super) fn redaction_pointers(fields: &[String]) -> Vec<String> {
+    fields
+        .iter()
+        .map(|field| format!("/{}", field.replace('.', "/")))
+        .collect()
+}
+
+pub(super) fn redact(document: &mut serde_json::Value, pointers: &[String]) {
+    for pointer in pointers {
+        if let Some(value) = document.pointer_mut(pointer) {
+            *value = json!("**REDACTED**");
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct Encoder {
+    pub(super) index: Template,
+    // Never rendered, so that it can't fail like the index did.
+    pub(super) index_fallback: Option<String>,
+    pub(super) metrics_index: Option<Template>,
+    pub(super) doc_type: Template,
+    pub(super) id: IdSource,
+    pub(super) remove_id_field: bool,
+    id_affixes: IdAffixes,
+    pub(super) non_string_id: NonStringIdPolicy,
+    pub(super) index_denylist: Vec<Regex>,
+    pub(super) tenant_key: Option<String>,
+    pub(super) normalize_field_names: Option<NormalizeFieldNamesConfig>,
+    max_fields: Option<(usize, ExtraFieldsPolicy)>,
+    pub(super) numeric_types: HashMap<String, NumericType>,
+    pub(super) field_types: Option<FieldTypes>,
+    pub(super) truncate_fields: HashMap<String, usize>,
+    pub(super) default_fields: Vec<(String, String)>,
+    pub(super) metadata_fields: Vec<(String, MetadataField)>,
+    vector_host: String,
+    pub(super) default_fields_precedence: DefaultFieldsPrecedence,
+    pub(super) future_timestamps: Option<(FutureTimestampPolicy, chrono::Duration)>,
+    pub(super) schema: Option<SchemaCheck>,
+    pub(super) max_array_depth: Option<(usize, ArrayNestingPolicy)>,
+    pub(super) level_routing: Option<LevelRouting>,
+    // The index of logs older than the duration, instead of `index`.
+    pub(super) warm_index: Option<(Template, chrono::Duration)>,
+    pub(super) max_line_bytes: Option<(usize, OversizedLinePolicy)>,
+    pub(super) sequence: Option<Arc<Sequence>>,
+    pub(super) flattened_fields: Vec<String>,
+    pub(super) pipelines: Option<Pipelines>,
+    pub(super) raw_action_key: Option<Atom>,
+    versioning: Option<Versioning>,
+    pub(super) data_stream: bool,
+    pub(super) bulk_action: BulkAction,
+    pub(super) detailed_metrics: bool,
+    compressed_sizes: Option<CompressedSizes>,
+    pub(super) emit_internal_events: bool,
+}
+
+#[derive(Clone)]
+pub(super) struct Versioning {
+    pub(super) key: Atom,
+    type_key: Option<Atom>,
+    default_type: VersionType,
+}
+
+impl Versioning {
+    /// Sets the version of the document from its `version_key` field, and its
+    /// version type from its `version_type_key` field if it has one. Returns
+    /// `None` if the event should be dropped.
+    fn set_version(&self, metadata: &mut serde_json::Value, event: &Event) -> Option<()> {
+        let log = event.as_log();
+        let version = match log.get(&self.key) {
+            Some(version) => version,
+            None => return Some(()),
+        };
+        let version = match version {
+            ValueKind::Integer(version) if *version >= 0 => *version as u64,
+            ValueKind::Bytes(_) => version
+                .to_string_lossy()
+                .parse::<u64>()
+                .map_err(|_| invalid_version("version", version))
+                .ok()?,
+            _ => {
+                invalid_version("version", version);
+                return None;
+            }
+        };
+
+        let version_type = match self.type_key.as_ref().and_then(|key| log.get(key)) {
+            Some(version_type) => VersionType::parse(&version_type.to_string_lossy())
+                .ok_or_else(|| invalid_version("version type", version_type))
+                .ok()?,
+            None => self.default_type,
+        };
+
+        metadata["version"] = json!(version);
+        metadata["version_type"] = json!(version_type.as_str());
+        Some(())
+    }
+}
+
+fn invalid_version(what: &str, value: &ValueKind) {
+    warn!(
+        message = "Invalid document version. Dropping event.",
+        %what,
+        value = %value.to_string_lossy(),
+        invalid_versions_counter = 1
+    );
+}
+
+#[derive(Clone)]
+pub(super) struct Pipelines {
+    pub(super) key: Option<Atom>,
+    by_value: HashMap<String, String>,
+    pub(super) default: Option<String>,
+}
+
+#[derive(Clone)]
+pub(super) struct LevelRouting {
+    pub(super) key: Atom,
+    // Keyed by lowercased level.
+    pub(super) indices: HashMap<String, Template>,
+}
+
+impl Encoder {
+    pub(super) fn new(config: &ElasticSearchConfig) -> Self {
+        let index = if let Some(idx) = &config.index {
+            Template::from(idx.as_str())
+        } else {
+            Template::from("vector-%Y.%m.%d")
+        };
+
+        Self {
+            index,
+            index_fallback: config.index_fallback.clone(),
+            metrics_index: config
+                .metrics_index
+                .as_ref()
+                .map(|index| Template::from(index.as_str())),
+            doc_type: Template::from(doc_type(config)),
+            id: IdSource::new(config),
+            remove_id_field: config.remove_id_field.unwrap_or(false),
+            id_affixes: IdAffixes {
+                prefix: config
+                    .id_prefix
+                    .as_ref()
+                    .map(|p| Template::from(p.as_str())),
+                suffix: config
+                    .id_suffix
+                    .as_ref()
+                    .map(|s| Template::from(s.as_str())),
+            },
+            non_string_id: config.non_string_id.unwrap_or(NonStringIdPolicy::Coerce),
+            index_denylist: config
+                .index_denylist
+                .iter()
+                .map(|pattern| glob_to_regex(pattern))
+                .collect(),
+            tenant_key: config.tenant_key.clone(),
+            normalize_field_names: config.normalize_field_names.clone(),
+            max_fields: config.max_fields_per_document.map(|max| {
+                let policy = config
+                    .extra_fields
+                    .clone()
+                    .unwrap_or(ExtraFieldsPolicy::DropExtra);
+                (max, policy)
+            }),
+            numeric_types: config.numeric_types.clone().unwrap_or_default(),
+            field_types: None,
+            truncate_fields: config.truncate_fields.clone().unwrap_or_default(),
+            default_fields: {
+                let mut fields = config
+                    .default_fields
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                // Overlapping defaults are merged in the same order every time.
+                fields.sort();
+                fields
+            },
+            default_fields_precedence: config
+                .default_fields_precedence
+                .unwrap_or(DefaultFieldsPrecedence::Event),
+            metadata_fields: {
+                let mut fields = config
+                    .metadata_fields
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                fields.sort();
+                fields
+            },
+            vector_host: hostname::get_hostname().unwrap_or_default(),
+            future_timestamps: match config.future_timestamps {
+                None | Some(FutureTimestampPolicy::Keep) => None,
+                Some(policy) => {
+                    let tolerance = config.future_timestamp_tolerance_secs.unwrap_or(60);
+                    Some((policy, chrono::Duration::seconds(tolerance as i64)))
+                }
+            },
+            schema: None,
+            max_array_depth: config.max_array_depth.map(|max| {
+                let policy = config.array_nesting.unwrap_or(ArrayNestingPolicy::Flatten);
+                (std::cmp::max(max, 1), policy)
+            }),
+            warm_index: match (&config.warm_index, config.warm_after_secs) {
+                (Some(index), Some(secs)) => Some((
+                    Template::from(index.as_str()),
+                    chrono::Duration::seconds(secs as i64),
+                )),
+                _ => None,
+            },
+            level_routing: config.level_routing.as_ref().map(|indices| LevelRouting {
+                key: config
+                    .level_key
+                    .as_ref()
+                    .map_or("level", String::as_str)
+                    .into(),
+                indices: indices
+                    .iter()
+                    .map(|(level, index)| (level.to_lowercase(), Template::from(index.as_str())))
+                    .collect(),
+            }),
+            max_line_bytes: config.max_line_bytes.map(|max| {
+                let policy = config.oversized_lines.unwrap_or(OversizedLinePolicy::Drop);
+                (max, policy)
+            }),
+            sequence: None,
+            flattened_fields: config.flattened_fields.clone().unwrap_or_default(),
+            raw_action_key: config
+                .raw_action_key
+                .as_ref()
+                .map(|key| key.as_str().into()),
+            data_stream: config.mode == Some(ElasticSearchMode::DataStream),
+            bulk_action: config.bulk_action.unwrap_or(BulkAction::Index),
+            versioning: config.version_key.as_ref().map(|key| Versioning {
+                key: key.as_str().into(),
+                type_key: config
+                    .version_type_key
+                    .as_ref()
+                    .map(|key| key.as_str().into()),
+                default_type: config.version_type.unwrap_or(VersionType::External),
+            }),
+            pipelines: if config.pipeline_map.is_some() || config.default_pipeline.is_some() {
+                Some(Pipelines {
+                    key: config.pipeline_key.as_ref().map(|key| key.as_str().into()),
+                    by_value: config.pipeline_map.clone().unwrap_or_default(),
+                    default: config.default_pipeline.clone(),
+                })
+            } else {
+                None
+            },
+            detailed_metrics: config.detailed_metrics.unwrap_or(false),
+            compressed_sizes: match config.compression.unwrap_or(Compression::Gzip) {
+                Compression::Gzip if config.detailed_metrics.unwrap_or(false) => {
+                    Some(CompressedSizes::new(config))
+                }
+                _ => None,
+            },
+            emit_internal_events: config.emit_internal_events.unwrap_or(false),
+        }
+    }
+
+    /// Formats the dates in every index, type and id template in the given
+    /// zone.
+    pub(super) fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        let zoned = |template: Template| template.with_timezone(timezone);
+        self.index = zoned(self.index);
+        self.metrics_index = self.metrics_index.map(zoned);
+        self.doc_type = zoned(self.doc_type);
+        self.id_affixes.prefix = self.id_affixes.prefix.map(zoned);
+        self.id_affixes.suffix = self.id_affixes.suffix.map(zoned);
+        self.warm_index = self.warm_index.map(|(index, after)| (zoned(index), after));
+        if let Some(routing) = &mut self.level_routing {
+            routing.indices = routing
+                .indices
+                .drain()
+                .map(|(level, index)| (level, zoned(index)))
+                .collect();
+        }
+        self
+    }
+
+    /// The action line of a document, with its index, type, id, version and
+    /// pipeline.
+    pub(super) fn action(&self, event: &Event, index: &Template) -> Option<serde_json::Value> {
+        let index = match (index.render_string(event), &self.index_fallback) {
+            (Ok(index), _) => index,
+            (Err(keys), Some(fallback)) => {
+                warn!(
+                    message = "Keys do not exist on the event. Writing to the fallback index.",
+                    ?keys,
+                    index = %fallback
+                );
+                fallback.clone()
+            }
+            (Err(keys), None) => {
+                warn!(
+                    message = "Keys do not exist on the event. Dropping event.",
+                    ?keys
+                );
+                return None;
+            }
+        };
+        self.check_denylist(&index)?;
+
+        // Data streams only take `create` actions, and have no types.
+        let (name, mut metadata) = if self.data_stream {
+            ("create", json!({ "_index": index }))
+        } else {
+            let doc_type = self
+                .doc_type
+                .render_string(event)
+                .map_err(|keys| {
+                    warn!(
+                        message = "Keys do not exist on the event. Dropping event.",
+                        ?keys
+                    );
+                })
+                .ok()?;
+            let name = self.bulk_action.as_str();
+            (name, json!({ "_index": index, "_type": doc_type }))
+        };
+        maybe_set_id(
+            &self.id,
+            &mut metadata,
+            event,
+            self.non_string_id,
+            &self.id_affixes,
+        )?;
+        if let Some(versioning) = &self.versioning {
+            versioning.set_version(&mut metadata, event)?;
+        }
+        if let Some(pipeline) = self.pipeline(event) {
+            metadata["pipeline"] = json!(pipeline);
+        }
+        Some(json!({ name: metadata }))
+    }
+
+    /// Returns `None` if the event should be dropped.
+    fn check_denylist(&self, index: &str) -> Option<()> {
+        if self.index_denylist.iter().any(|re| re.is_match(index)) {
+            error!(
+                message = "Refusing to write to denylisted index. Dropping event.",
+                %index,
+                denylisted_events_counter = 1
+            );
+            return None;
+        }
+        Some(())
+    }
+
+    /// The index of a log event, which may be routed by its level.
+    pub(super) fn log_index(&self, event: &Event) -> &Template {
+        self.log_index_at(event, Utc::now())
+    }
+
+    /// The index of a log: the index of its level, if routed by level, and
+    /// otherwise the warm index if it's older than the warm age at `now`.
+    pub(super) fn log_index_at(&self, event: &Event, now: DateTime<Utc>) -> &Template {
+        let by_level = self.level_routing.as_ref().and_then(|routing| {
+            event
+                .as_log()
+                .get(&routing.key)
+                .and_then(|level| routing.indices.get(&level.to_string_lossy().to_lowercase()))
+        });
+        if let Some(index) = by_level {
+            return index;
+        }
+
+        match (&self.warm_index, event.as_log().get(&event::TIMESTAMP)) {
+            (Some((index, age)), Some(ValueKind::Timestamp(timestamp)))
+                if *timestamp < now - *age =>
+            {
+                index
+            }
+            _ => &self.index,
+        }
+    }
+
+    /// The ingest pipeline a log event is sent through, picked by the value
+    /// of its `pipeline_key` field.
+    pub(super) fn pipeline(&self, event: &Event) -> Option<&str> {
+        let pipelines = self.pipelines.as_ref()?;
+        pipelines
+            .key
+            .as_ref()
+            .and_then(|key| event.as_log().get(key))
+            .and_then(|value| pipelines.by_value.get(&*value.to_string_lossy()))
+            .or_else(|| pipelines.default.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The `metadata_fields` of the event, as fields to add to its document.
+    pub(super) fn metadata(&self, event: &Event) -> Vec<(String, String)> {
+        self.metadata_fields
+            .iter()
+            .map(|(key, field)| {
+                let value = match field {
+                    MetadataField::EventType => match event {
+                        Event::Log(_) => "log",
+                        Event::Metric(_) => "metric",
+                    },
+                    MetadataField::VectorHost => self.vector_host.as_str(),
+                    MetadataField::VectorVersion => env!("CARGO_PKG_VERSION"),
+                };
+                (key.clone(), value.to_string())
+            })
+            .collect()
+    }
+
+    pub(super) fn tenant(&self, event: &Event) -> Option<String> {
+        let key = self.tenant_key.as_ref()?;
+        let log = match event {
+            Event::Log(log) => log,
+            Event::Metric(_) => return None,
+        };
+        log.get(&key.as_str().into())
+            .map(|value| value.to_string_lossy())
+    }
+
+    /// Encodes the event as bulk lines, counting the events that encode and
+    /// the ones that are dropped.
+    pub(super) fn encode_event(&self, event: Event) -> Option<Vec<u8>> {
+        let encoded = self.encode(event);
+        if self.emit_internal_events {
+            let name = if encoded.is_some() {
+                "events_encoded_total"
+            } else {
+                "events_dropped_total"
+            };
+            internal_events::emit(counter(name, 1.0));
+        }
+        encoded
+    }
+
+    pub(super) fn encode(&self, event: Event) -> Option<Vec<u8>> {
+        let metadata = self.metadata(&event);
+        let (mut event, index) = match event {
+            Event::Metric(metric) => {
+                let index = self.metrics_index.as_ref().unwrap_or(&self.index);
+                (Event::Log(metric_to_log(metric)), index)
+            }
+            event => {
+                let index = self.log_index(&event);
+                (event, index)
+            }
+        };
+
+        if let Some((policy, tolerance)) = self.future_timestamps {
+            limit_future_timestamp(event.as_mut_log(), policy, tolerance, Utc::now())?;
+        }
+        if self.data_stream {
+            add_data_stream_timestamp(event.as_mut_log());
+        }
+
+        let raw_action = match &self.raw_action_key {
+            Some(key) => event.as_mut_log().remove(key),
+            None => None,
+        };
+        let pre_rendered = raw_action.is_some();
+        // Pre-rendered actions are always followed by a whole document.
+        let bulk_action = if pre_rendered {
+            BulkAction::Index
+        } else {
+            self.bulk_action
+        };
+        let action = match raw_action {
+            Some(raw) => {
+                let action = parse_raw_action(&raw.to_string_lossy())
+                    .map_err(|reason| {
+                        warn!(
+                            message = "Invalid pre-rendered bulk action. Dropping event.",
+                            %reason,
+                            invalid_raw_actions_counter = 1
+                        );
+                    })
+                    .ok()?;
+                let index = action
+                    .as_object()
+                    .and_then(|action| action.values().next())
+                    .and_then(|metadata| metadata["_index"].as_str());
+                if let Some(index) = index {
+                    self.check_denylist(index)?;
+                }
+                action
+            }
+            None => self.action(&event, index)?,
+        };
+
+        let mut body = serde_json::to_vec(&action).unwrap();
+        body.push(b'\n');
+
+        let mut log = event.into_log();
+        if self.remove_id_field && !pre_rendered && has_id(&action) {
+            if let IdSource::Key(key, _) = &self.id {
+                for field in key.fields() {
+                    log.remove(&field.as_str().into());
+                }
+            }
+        }
+        if let Some(normalize) = &self.normalize_field_names {
+            log = normalize.normalize(log)?;
+        }
+        merge_default_fields(
+            &mut log,
+            &self.default_fields,
+            self.default_fields_precedence,
+        );
+        // Metadata never replaces a field of the event.
+        merge_default_fields(&mut log, &metadata, DefaultFieldsPrecedence::Event);
+        coerce_numbers(&mut log, &self.numeric_types);
+        if let Some(field_types) = &self.field_types {
+            field_types.apply(&mut log)?;
+        }
+        truncate_fields(&mut log, &self.truncate_fields);
+        if let Some((max, policy)) = &self.max_fields {
+            log = cap_fields(log, *max, policy)?;
+        }
+        if let Some(sequence) = &self.sequence {
+            log.insert_explicit(sequence.field.clone(), ValueKind::Integer(sequence.next()));
+        }
+
+        let document = if self.schema.is_none()
+            && self.max_array_depth.is_none()
+            && self.flattened_fields.is_empty()
+        {
+            serde_json::to_vec(&log.unflatten()).unwrap()
+        } else {
+            let mut document = unflatten_except(&log, &self.flattened_fields);
+            if let Some((max, policy)) = self.max_array_depth {
+                limit_array_nesting(&mut document, max, policy);
+            }
+            if let Some(schema) = &self.schema {
+                schema.check(&log, &document)?;
+            }
+            serde_json::to_vec(&document).unwrap()
+        };
+        let document = match self.max_line_bytes {
+            Some((max, policy)) if document.len() > max => limit_line(document, max, policy)?,
+            _ => document,
+        };
+        if self.detailed_metrics {
+            trace!(elasticsearch_document_bytes_histogram = document.len() as u64);
+        }
+        if let Some(compressed) = self
+            .compressed_sizes
+            .as_ref()
+            .and_then(|sizes| sizes.sample(&document))
+        {
+            trace!(elasticsearch_document_compressed_bytes_histogram = compressed as u64);
+        }
+
+        if bulk_action == BulkAction::Update && !self.data_stream {
+            // Updates carry the document as a partial one, creating it if it
+            // doesn't exist yet.
+            body.extend_from_slice(br#"{"doc":"#);
+            body.extend_from_slice(&document);
+            body.extend_from_slice(br#","doc_as_upsert":true}"#);
+        } else {
+            body.extend_from_slice(&document);
+        }
+        body.push(b'\n');
+        Some(body)
+    }
+}
+
+/// Whether a bulk action identifies its document.
+fn has_id(action: &serde_json::Value) -> bool {
+    action
+        .as_object()
+        .and_then(|action| action.values().next())
+        .map_or(false, |metadata| metadata.get("_id").is_some())
+}
+
+/// Compresses one in every `COMPRESSED_SIZE_SAMPLE_INTERVAL` documents on its
+/// own, at the level batches are compressed at, to sample how well documents
+/// compress without compressing every one of them twice.
+#[derive(Clone)]
+pub(super) struct CompressedSizes {
+    pub(super) level: flate2::Compression,
+    pub(super) documents: Arc<AtomicUsize>,
+}
+
+const COMPRESSED_SIZE_SAMPLE_INTERVAL: usize = 100;
+
+impl CompressedSizes {
+    pub(super) fn new(config: &ElasticSearchConfig) -> Self {
+        let level = config
+            .compression_level
+            .map(flate2::Compression::new)
+            .unwrap_or_default();
+        Self {
+            level,
+            documents: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(super) fn sample(&self, document: &[u8]) -> Option<usize> {
+        if self.documents.fetch_add(1, Ordering::Relaxed) % COMPRESSED_SIZE_SAMPLE_INTERVAL != 0 {
+            return None;
+        }
+        Some(compressed_len(document, self.level))
+    }
+}
+
+pub(super) fn compressed_len(document: &[u8], level: flate2::Compression) -> usize {
+    let mut gzip = GzEncoder::new(Vec::new(), level);
+    gzip.write_all(document)
+        .and_then(|_| gzip.finish())
+        .map_or(0, |compressed| compressed.len())
+}
+
+// Parses an action line built upstream of vector. Only the actions that are
+// followed by a whole document are allowed.
+fn parse_raw_action(raw: &str) -> Result<serde_json::Value, String> {
+    let action = serde_json::from_str::<serde_json::Value>(raw)
+        .map_err(|error| format!("not valid JSON: {}", error))?;
+    let object = action
+        .as_object()
+        .ok_or_else(|| "not a JSON object".to_string())?;
+    if object.len() != 1 {
+        return Err(format!("has {} actions instead of one", object.len()));
+    }
+    let (name, metadata) = object.iter().next().unwrap();
+    if name != "index" && name != "create" {
+        return Err(format!(
+            "unsupported action {:?}, only \"index\" and \"create\" are supported",
+            name
+        ));
+    }
+    if !metadata.is_object() {
+        return Err(format!("the {:?} metadata is not an object", name));
+    }
+    Ok(action)
+}
+
+// Unflattens `log` into a document, except that the fields under each of
+// `flattened` are kept as a single object keyed by their dotted paths, for
+// fields mapped with the `flattened` type.
+fn unflatten_except(log: &LogEvent, flattened: &[String]) -> serde_json::Value {
+    if flattened.is_empty() {
+        return serde_json::to_value(log.clone().unflatten()).unwrap();
+    }
+
+    let mut rest = log.clone();
+    let mut objects = Vec::new();
+    for field in flattened {
+        let prefix = format!("{}.", field);
+        let keys = log
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            continue;
+        }
+
+        let mut object = serde_json::Map::new();
+        for key in keys {
+            let value = rest.remove(&key).unwrap();
+            object.insert(
+                key[prefix.len()..].to_string(),
+                serde_json::to_value(value).unwrap(),
+            );
+        }
+        objects.push((field, object));
+    }
+
+    let mut document = serde_json::to_value(rest.unflatten()).unwrap();
+    for (field, object) in objects {
+        document[field.as_str()] = serde_json::Value::Object(object);
+    }
+    document
+}
+
+/// How many sequence numbers are reserved in the state file at a time, so it
+/// isn't written for every event. The next block is reserved halfway through
+/// the current one, so up to one and a half blocks of numbers are skipped
+/// after a restart.
+pub(super) const SEQUENCE_RESERVATION: i64 = 1000;
+
+/// A counter stamped into every document, in the order events are encoded.
+pub(super) struct Sequence {
+    pub(super) field: Atom,
+    pub(super) state: Arc<Mutex<SequenceState>>,
+    // Hands reservations to the thread persisting them, so that encoding
+    // never waits on the disk.
+    reservations: Option<Mutex<std::sync::mpsc::Sender<i64>>>,
+}
+
+pub(super) struct SequenceState {
+    pub(super) next: i64,
+    // Numbers below this are covered by the persisted state, so a later run
+    // won't hand them out again.
+    reserved: i64,
+    // Whether the block after `reserved` is being persisted.
+    reserving: bool,
+}
+
+impl Sequence {
+    pub(super) fn new(field: &str) -> Self {
+        Self {
+            field: field.into(),
+            state: Arc::new(Mutex::new(SequenceState {
+                next: 0,
+                reserved: 0,
+                reserving: false,
+            })),
+            reservations: None,
+        }
+    }
+
+    /// Continues from where the last run using `path` left off.
+    pub(super) fn persisted(field: &str, path: &Path) -> Result<Self, String> {
+        let next = match std::fs::read_to_string(path) {
+            Ok(contents) => contents
+                .trim()
+                .parse()
+                .map_err(|error| format!("Could not parse sequence state {:?}: {}", path, error))?,
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(error) => {
+                return Err(format!(
+                    "Could not read sequence state {:?}: {}",
+                    path, error
+                ))
+            }
+        };
+        // The first block is reserved up front, as the sink is built.
+        let reserved = next + SEQUENCE_RESERVATION;
+        std::fs::write(path, reserved.to_string())
+            .map_err(|error| format!("Could not persist sequence state {:?}: {}", path, error))?;
+
+        let state = Arc::new(Mutex::new(SequenceState {
+            next,
+            reserved,
+            reserving: false,
+        }));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let path = path.to_path_buf();
+        let persisting = Arc::clone(&state);
+        std::thread::Builder::new()
+            .name("es-sequence".into())
+            .spawn(move || persist_reservations(&path, &persisting, rx))
+            .map_err(|error| format!("Could not start persisting sequence state: {}", error))?;
+
+        Ok(Self {
+            field: field.into(),
+            state,
+            reservations: Some(Mutex::new(tx)),
+        })
+    }
+
+    pub(super) fn next(&self) -> i64 {
+        let mut state = self.state.lock().unwrap();
+        let value = state.next;
+        state.next += 1;
+
+        if let Some(reservations) = &self.reservations {
+            if !state.reserving && state.reserved - value <= SEQUENCE_RESERVATION / 2 {
+                state.reserving = true;
+                let reserved = state.reserved + SEQUENCE_RESERVATION;
+                // The thread only stops once the sequence is dropped.
+                let _ = reservations.lock().unwrap().send(reserved);
+            }
+        }
+
+        value
+    }
+}
+
+/// Writes each reservation to `path` until the sequence is dropped. Numbers
+/// are handed out past a reservation that couldn't be persisted anyway,
+/// rather than stopping the sink.
+fn persist_reservations(
+    path: &Path,
+    state: &Mutex<SequenceState>,
+    reservations: std::sync::mpsc::Receiver<i64>,
+) {
+    for reserved in reservations {
+        if let Err(error) = std::fs::write(path, reserved.to_string()) {
+            error!(
+                message = "Could not persist sequence state.",
+                ?path,
+                %error
+            );
+        }
+        let mut state = state.lock().unwrap();
+        state.reserved = reserved;
+        state.reserving = false;
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct SchemaCheck {
+    pub(super) schema: Arc<JsonSchema>,
+    pub(super) violations: SchemaViolationPolicy,
+    pub(super) dead_letters: Option<Arc<DeadLetters>>,
+}
+
+impl SchemaCheck {
+    /// Returns `None` if the document should be dropped.
+    fn check(&self, log: &LogEvent, document: &serde_json::Value) -> Option<()> {
+        let reason = match self.schema.validate(document) {
+            Ok(()) => return Some(()),
+            Err(reason) => reason,
+        };
+
+        match self.violations {
+            SchemaViolationPolicy::Drop => {
+                warn!(
+                    message = "Document does not match schema. Dropping event.",
+                    %reason,
+                    schema_violations_counter = 1
+                );
+                None
+            }
+            SchemaViolationPolicy::Dlq => {
+                warn!(
+                    message = "Document does not match schema. Sending event to dead letter sink.",
+                    %reason,
+                    schema_violations_counter = 1
+                );
+                if let Some(dead_letters) = &self.dead_letters {
+                    dead_letters.send_events(vec![Event::Log(log.clone())]);
+                }
+                None
+            }
+            SchemaViolationPolicy::PassWithWarning => {
+                warn!(
+                    message = "Document does not match schema.",
+                    %reason,
+                    schema_violations_counter = 1
+                );
+                Some(())
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct FieldTypes {
+    pub(super) types: HashMap<String, TypeHint>,
+    pub(super) violations: SchemaViolationPolicy,
+    pub(super) dead_letters: Option<Arc<DeadLetters>>,
+}
+
+impl FieldTypes {
+    /// Coerces the fields of the log to their types. Returns `None` if the
+    /// event should be dropped.
+    fn apply(&self, log: &mut LogEvent) -> Option<()> {
+        for (field, hint) in &self.types {
+            let key = field.as_str().into();
+            let value = match log.get(&key) {
+                Some(value) => value,
+                None => continue,
+            };
+            if let Some(coerced) = coerce_to_type_hint(value, *hint) {
+                log.insert_explicit(key, coerced);
+                continue;
+            }
+
+            match self.violations {
+                SchemaViolationPolicy::Drop => {
+                    warn!(
+                        message = "Field can't be coerced to its type. Dropping event.",
+                        %field,
+                        hint = ?hint,
+                        field_type_violations_counter = 1
+                    );
+                    return None;
+                }
+                SchemaViolationPolicy::Dlq => {
+                    warn!(
+                        message = "Field can't be coerced to its type. Sending event to dead letter sink.",
+                        %field,
+                        hint = ?hint,
+                        field_type_violations_counter = 1
+                    );
+                    if let Some(dead_letters) = &self.dead_letters {
+                        dead_letters.send_events(vec![Event::Log(log.clone())]);
+                    }
+                    return None;
+                }
+                SchemaViolationPolicy::PassWithWarning => {
+                    warn!(
+                        message = "Field can't be coerced to its type.",
+                        %field,
+                        hint = ?hint,
+                        field_type_violations_counter = 1
+                    );
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+/// The value as the type, or `None` if it doesn't convert losslessly.
+pub(super) fn coerce_to_type_hint(value: &ValueKind, hint: TypeHint) -> Option<ValueKind> {
+    match (hint, value) {
+        (TypeHint::Integer, ValueKind::Integer(integer)) => Some(ValueKind::Integer(*integer)),
+        (TypeHint::Integer, ValueKind::Float(float)) => {
+            if float.fract() == 0.0 && float.abs() <= MAX_SAFE_INTEGER {
+                Some(ValueKind::Integer(*float as i64))
+            } else {
+                None
+            }
+        }
+        (TypeHint::Integer, ValueKind::Bytes(bytes)) => std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|string| string.trim().parse::<i64>().ok())
+            .map(ValueKind::Integer),
+        (TypeHint::Float, ValueKind::Float(float)) => Some(ValueKind::Float(*float)),
+        (TypeHint::Float, ValueKind::Integer(integer)) => Some(ValueKind::Float(*integer as f64)),
+        (TypeHint::Float, ValueKind::Bytes(bytes)) => std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|string| string.trim().parse::<f64>().ok())
+            .filter(|float| float.is_finite())
+            .map(ValueKind::Float),
+        (TypeHint::Keyword, ValueKind::Bytes(bytes)) => Some(ValueKind::Bytes(bytes.clone())),
+        (TypeHint::Keyword, value) => Some(ValueKind::Bytes(value.to_string_lossy().into())),
+        (TypeHint::Boolean, ValueKind::Boolean(boolean)) => Some(ValueKind::Boolean(*boolean)),
+        (TypeHint::Boolean, ValueKind::Bytes(bytes)) => match &bytes[..] {
+            b"true" => Some(ValueKind::Boolean(true)),
+            b"false" => Some(ValueKind::Boolean(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Elasticsearch 7 indices are typeless, so `_doc` is the only type it accepts
+// without deprecation warnings, or errors when `include_type_name=false`.
+pub(super) fn doc_type(config: &ElasticSearchConfig) -> String {
+    let doc_type = config.doc_type.clone().unwrap_or("_doc".into());
+    if config.api_version != Some(ApiVersion::V7) || doc_type == "_doc" {
+        return doc_type;
+    }
+
+    match config.custom_doc_type.unwrap_or(CustomDocTypePolicy::Warn) {
+        CustomDocTypePolicy::Warn => {
+            warn!(
+                message = "Elasticsearch 7 only supports the `_doc` type; sending custom doc_type anyway.",
+                %doc_type
+            );
+            doc_type
+        }
+        CustomDocTypePolicy::Coerce => {
+            warn!(
+                message = "Elasticsearch 7 only supports the `_doc` type; using it instead of custom doc_type.",
+                %doc_type
+            );
+            "_doc".into()
+        }
+    }
+}
+
+// Metrics become documents with their name, type, value and tags, so that a
+// single batch can mix them freely with logs.
+fn metric_to_log(metric: Metric) -> LogEvent {
+    let mut log = Event::new_empty_log().into_log();
+
+    let (kind, name, timestamp, tags) = match metric {
+        Metric::Counter {
+            name,
+            val,
+            timestamp,
+            tags,
+        } => {
+            log.insert_explicit("value".into(), val.into());
+            ("counter", name, timestamp, tags)
+        }
+        Metric::Histogram {
+            name,
+            val,
+            sample_rate,
+            timestamp,
+            tags,
+        } => {
+            log.insert_explicit("value".into(), val.into());
+            log.insert_explicit("sample_rate".into(), sample_rate.into());
+            ("histogram", name, timestamp, tags)
+        }
+        Metric::Gauge {
+            name,
+            val,
+            direction,
+            timestamp,
+            tags,
+        } => {
+            log.insert_explicit("value".into(), val.into());
+            if let Some(direction) = direction {
+                let direction = match direction {
+                    event::metric::Direction::Plus => "plus",
+                    event::metric::Direction::Minus => "minus",
+                };
+                log.insert_explicit("direction".into(), direction.into());
+            }
+            ("gauge", name, timestamp, tags)
+        }
+        Metric::Set {
+            name,
+            val,
+            timestamp,
+            tags,
+        } => {
+            log.insert_explicit("value".into(), val.into());
+            ("set", name, timestamp, tags)
+        }
+    };
+
+    log.insert_explicit("name".into(), name.into());
+    log.insert_explicit("type".into(), kind.into());
+    if let Some(timestamp) = timestamp {
+        log.insert_explicit(event::TIMESTAMP.clone(), timestamp.into());
+    }
+    for (key, value) in tags.into_iter().flatten() {
+        log.insert_explicit(format!("tags.{}", key).into(), value.into());
+    }
+
+    log
+}
+
+// Translates a glob pattern, where `*` matches any run of characters and `?`
+// matches a single character, into an anchored regex.
+fn glob_to_regex(pattern: &str) -> Regex {
+    let mut re = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+
+    Regex::new(&re).expect("escaped glob pattern is a valid regex")
+}
+
+// Keeps the first `max` fields in name order and applies `policy` to the
+// rest. Each flattened field counts as one.
+fn limit_line(document: Vec<u8>, max: usize, policy: OversizedLinePolicy) -> Option<Vec<u8>> {
+    let bytes = document.len();
+    if policy == OversizedLinePolicy::Truncate {
+        if let Some(document) = truncate_document(&document, max) {
+            warn!(
+                message = "Document line is too long. Truncated its longest strings.",
+                bytes,
+                max,
+                oversized_lines_counter = 1
+            );
+            return Some(document);
+        }
+    }
+
+    error!(
+        message = "Document line is too long. Dropping event.",
+        bytes,
+        max,
+        oversized_lines_counter = 1
+    );
+    None
+}
+
+// Shortens the document's longest string until the document fits, as cutting
+// the serialized line itself would leave invalid JSON. Fails if the document
+// doesn't fit even with every string emptied.
+fn truncate_document(document: &[u8], max: usize) -> Option<Vec<u8>> {
+    let mut value = serde_json::from_slice::<serde_json::Value>(document).ok()?;
+    let mut len = document.len();
+
+    while len > max {
+        let mut longest = None;
+        find_longest_string(&value, String::new(), &mut longest);
+        let (pointer, string_len) = longest.filter(|(_, string_len)| *string_len > 0)?;
+
+        if let Some(serde_json::Value::String(string)) = value.pointer_mut(&pointer) {
+            let mut keep = string_len.saturating_sub(len - max);
+            while !string.is_char_boundary(keep) {
+                keep -= 1;
+            }
+            string.truncate(keep);
+        }
+        len = serde_json::to_vec(&value).ok()?.len();
+    }
+
+    serde_json::to_vec(&value).ok()
+}
+
+fn find_longest_string(
+    value: &serde_json::Value,
+    pointer: String,
+    longest: &mut Option<(String, usize)>,
+) {
+    use serde_json::Value;
+
+    match value {
+        Value::String(string) => {
+            if longest
+                .as_ref()
+                .map_or(true, |(_, len)| string.len() > *len)
+            {
+                *longest = Some((pointer, string.len()));
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                find_longest_string(value, format!("{}/{}", pointer, key), longest);
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                find_longest_string(item, format!("{}/{}", pointer, i), longest);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn limit_array_nesting(document: &mut serde_json::Value, max: usize, policy: ArrayNestingPolicy) {
+    let limited = limit_array_depth(document, 0, max, policy);
+    if limited > 0 {
+        warn!(
+            message = "Arrays are nested too deeply.",
+            ?policy,
+            limited,
+            array_nesting_limited_counter = limited
+        );
+    }
+}
+
+// Returns the number of arrays that were nested too deeply.
+fn limit_array_depth(
+    value: &mut serde_json::Value,
+    depth: usize,
+    max: usize,
+    policy: ArrayNestingPolicy,
+) -> usize {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => map
+            .values_mut()
+            .map(|value| limit_array_depth(value, 0, max, policy))
+            .sum(),
+        Value::Array(items) => {
+            let depth = depth + 1;
+            let mut limited = 0;
+            if depth == max && items.iter().any(Value::is_array) {
+                let nested = std::mem::replace(items, Vec::new());
+                for item in nested {
+                    match item {
+                        Value::Array(_) if policy == ArrayNestingPolicy::Stringify => {
+                            limited += 1;
+                            items.push(Value::String(item.to_string()));
+                        }
+                        Value::Array(_) => {
+                            limited += 1;
+                            flatten_array(item, items);
+                        }
+                        item => items.push(item),
+                    }
+                }
+                // Objects brought up by flattening start their own nesting.
+                for item in items.iter_mut().filter(|item| item.is_object()) {
+                    limited += limit_array_depth(item, 0, max, policy);
+                }
+            } else {
+                for item in items.iter_mut() {
+                    limited += limit_array_depth(item, depth, max, policy);
+                }
+            }
+            limited
+        }
+        _ => 0,
+    }
+}
+
+fn flatten_array(value: serde_json::Value, into: &mut Vec<serde_json::Value>) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items {
+                flatten_array(item, into);
+            }
+        }
+        value => into.push(value),
+    }
+}
+
+// Integers beyond this can't all be represented by a float, so floats past it
+// can't be trusted to hold the integer they look like.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+pub(super) fn coerce_numbers(log: &mut LogEvent, types: &HashMap<String, NumericType>) {
+    for (field, numeric_type) in types {
+        let key = field.as_str().into();
+        let coerced = match (log.get(&key), numeric_type) {
+            (Some(ValueKind::Integer(integer)), NumericType::Float) => {
+                ValueKind::Float(*integer as f64)
+            }
+            (Some(ValueKind::Float(float)), NumericType::Integer) => {
+                if float.fract() != 0.0 || float.abs() > MAX_SAFE_INTEGER {
+                    warn!(
+                        message = "Float can't be safely sent as an integer. Leaving it as is.",
+                        %field,
+                        value = *float,
+                        numeric_coercion_failures_counter = 1
+                    );
+                    continue;
+                }
+                ValueKind::Integer(*float as i64)
+            }
+            _ => continue,
+        };
+        log.insert_explicit(key, coerced);
+    }
+}
+
+lazy_static! {
+    static ref DATA_STREAM_TIMESTAMP: Atom = Atom::from("@timestamp");
+}
+
+/// Data streams require documents to have an `@timestamp` field.
+fn add_data_stream_timestamp(log: &mut LogEvent) {
+    if log.get(&DATA_STREAM_TIMESTAMP).is_none() {
+        if let Some(timestamp) = log.get(&event::TIMESTAMP).cloned() {
+            log.insert_explicit(DATA_STREAM_TIMESTAMP.clone(), timestamp);
+        }
+    }
+}
+
+/// Clamps the timestamp of events from further in the future than the
+/// tolerance to `now`, or drops them, before their index is rendered from it.
+/// Returns `None` if the event should be dropped.
+pub(super) fn limit_future_timestamp(
+    log: &mut LogEvent,
+    policy: FutureTimestampPolicy,
+    tolerance: chrono::Duration,
+    now: DateTime<Utc>,
+) -> Option<()> {
+    let timestamp = match log.get(&event::TIMESTAMP) {
+        Some(ValueKind::Timestamp(timestamp)) if *timestamp > now + tolerance => *timestamp,
+        _ => return Some(()),
+    };
+    match policy {
+        FutureTimestampPolicy::Keep => Some(()),
+        FutureTimestampPolicy::Clamp => {
+            debug!(
+                message = "Clamping future timestamp to now.",
+                %timestamp,
+                clamped_future_timestamps_counter = 1
+            );
+            log.insert_explicit(event::TIMESTAMP.clone(), now.into());
+            Some(())
+        }
+        FutureTimestampPolicy::Drop => {
+            warn!(
+                message = "Timestamp is in the future. Dropping event.",
+                %timestamp,
+                dropped_future_timestamps_counter = 1
+            );
+            None
+        }
+    }
+}
+
+/// Adds the default fields to the event. A default overlaps an event field
+/// when they are the same field, or when one is nested under the other once
+/// unflattened, e.g. `host` and `host.name`. Overlapping fields are never
+/// both sent: with `Event` precedence the default is skipped, and with
+/// `Defaults` precedence the event fields it overlaps are removed.
+pub(super) fn merge_default_fields(
+    log: &mut LogEvent,
+    defaults: &[(String, String)],
+    precedence: DefaultFieldsPrecedence,
+) {
+    for (field, value) in defaults {
+        let overlapping = log
+            .keys()
+            .filter(|key| paths_overlap(key, field))
+            .cloned()
+            .collect::<Vec<_>>();
+        match precedence {
+            DefaultFieldsPrecedence::Event if !overlapping.is_empty() => continue,
+            DefaultFieldsPrecedence::Event => {}
+            DefaultFieldsPrecedence::Defaults => {
+                for key in &overlapping {
+                    log.remove(key);
+                }
+            }
+        }
+        log.insert_explicit(field.as_str().into(), value.as_str().into());
+    }
+}
+
+pub(super) fn paths_overlap(a: &str, b: &str) -> bool {
+    let nested = |outer: &str, inner: &str| {
+        inner.starts_with(outer) && inner[outer.len()..].starts_with('.')
+    };
+    a == b || nested(a, b) || nested(b, a)
+}
+
+/// Cuts string fields longer than their limit down to the limit in
+/// characters followed by an ellipsis, and marks documents with truncated
+/// fields with `_truncated: true`.
+pub(super) fn truncate_fields(log: &mut LogEvent, limits: &HashMap<String, usize>) {
+    let mut truncated = false;
+    for (field, max) in limits {
+        let key = field.as_str().into();
+        let value = match log.get(&key) {
+            Some(value @ ValueKind::Bytes(_)) => value.to_string_lossy(),
+            _ => continue,
+        };
+        if let Some((cut, _)) = value.char_indices().nth(*max) {
+            debug!(
+                message = "Truncating oversized field.",
+                %field,
+                chars = value.chars().count(),
+                truncated_fields_counter = 1
+            );
+            log.insert_explicit(key, format!("{}…", &value[..cut]).into());
+            truncated = true;
+        }
+    }
+    if truncated {
+        log.insert_explicit("_truncated".into(), true.into());
+    }
+}
+
+pub(super) fn cap_fields(
+    mut log: LogEvent,
+    max: usize,
+    policy: &ExtraFieldsPolicy,
+) -> Option<LogEvent> {
+    let mut names = log.keys().map(|key| key.to_string()).collect::<Vec<_>>();
+    if names.len() <= max {
+        return Some(log);
+    }
+    names.sort();
+    let extra = names.split_off(max);
+
+    match policy {
+        ExtraFieldsPolicy::DropExtra => {
+            warn!(
+                message = "Document has too many fields. Dropping extra fields.",
+                dropped = extra.len(),
+                field_cap_exceeded_counter = 1
+            );
+            for name in extra {
+                log.remove(&name.into());
+            }
+        }
+        ExtraFieldsPolicy::Error => {
+            error!(
+                message = "Document has too many fields. Dropping event.",
+                fields = max + extra.len(),
+                field_cap_exceeded_counter = 1
+            );
+            return None;
+        }
+        ExtraFieldsPolicy::NestExtraUnder(key) => {
+            warn!(
+                message = "Document has too many fields. Nesting extra fields.",
+                %key,
+                nested = extra.len(),
+                field_cap_exceeded_counter = 1
+            );
+            for name in extra {
+                if let Some(value) = log.remove(&name.as_str().into()) {
+                    log.insert_explicit(format!("{}.{}", key, name).into(), value);
+                }
+            }
+        }
+    }
+
+    Some(log)
+}
+
+// Elasticsearch rejects documents whose `_id` is longer than this many bytes.
+pub(super) const MAX_ID_BYTES: usize = 512;
+
+/// Templates rendered around the `id_key` value, e.g. to namespace ids of
+/// tenants sharing an index.
+#[derive(Clone, Default)]
+pub(super) struct IdAffixes {
+    pub(super) prefix: Option<Template>,
+    pub(super) suffix: Option<Template>,
+}
+
+impl IdAffixes {
+    fn wrap(&self, id: String, event: &Event) -> Option<String> {
+        let render = |template: &Option<Template>| match template {
+            Some(template) => template.render_string(event).map_err(|keys| {
+                warn!(
+                    message =
+                        "Keys for id prefix or suffix do not exist on the event. Dropping event.",
+                    ?keys
+                );
+            }),
+            None => Ok(String::new()),
+        };
+
+        let id = format!(
+            "{}{}{}",
+            render(&self.prefix).ok()?,
+            id,
+            render(&self.suffix).ok()?
+        );
+        if id.len() > MAX_ID_BYTES {
+            warn!(
+                message = "id is too long. Dropping event.",
+                bytes = id.len(),
+                max = MAX_ID_BYTES,
+                oversized_ids_counter = 1
+            );
+            return None;
+        }
+
+        Some(id)
+    }
+}
+
+/// Where the `_id` of a document comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum IdSource {
+    None,
+    /// The values of the `id_key` fields, joined with the separator.
+    Key(IdKey, String),
+    /// A hash of the event, so that identical events get the same id.
+    Hash,
+}
+
+impl IdSource {
+    pub(super) fn new(config: &ElasticSearchConfig) -> Self {
+        match (&config.id_key, config.id_strategy) {
+            (Some(key), _) => {
+                let separator = config.id_key_separator.as_ref().map_or(":", String::as_str);
+                IdSource::Key(key.clone(), separator.into())
+            }
+            (None, Some(IdStrategy::Hash)) => IdSource::Hash,
+            (None, _) => IdSource::None,
+        }
+    }
+}
+
+// Events are flat, so an `id_key` naming a nested object never resolves to a
+// value and no `_id` is set; only scalars are subject to `non_string`. A
+// composite key sets no `_id` unless every one of its fields has a value.
+pub(super) fn maybe_set_id(
+    source: &IdSource,
+    doc: &mut serde_json::Value,
+    event: &Event,
+    non_string: NonStringIdPolicy,
+    affixes: &IdAffixes,
+) -> Option<()> {
+    let (key, separator) = match source {
+        IdSource::None => return Some(()),
+        IdSource::Key(key, separator) => (key, separator),
+        IdSource::Hash => {
+            let val = affixes.wrap(hash_id(event), event)?;
+            doc.as_object_mut()
+                .unwrap()
+                .insert("_id".into(), json!(val));
+            return Some(());
+        }
+    };
+    let values = key
+        .fields()
+        .iter()
+        .map(|field| event.as_log().get(&field.as_str().into()))
+        .collect::<Option<Vec<_>>>();
+
+    if let Some(values) = values {
+        let is_string = values.iter().all(|val| match val {
+            ValueKind::Bytes(_) => true,
+            _ => false,
+        });
+
+        if !is_string {
+            match non_string {
+                NonStringIdPolicy::Coerce => {
+                    debug!(
+                        message = "coercing non-string id value.",
+                        coerced_ids_counter = 1
+                    );
+                }
+                NonStringIdPolicy::Skip => {
+                    debug!(
+                        message = "skipping non-string id value.",
+                        skipped_ids_counter = 1
+                    );
+                    return Some(());
+                }
+                NonStringIdPolicy::Error => {
+                    warn!(
+                        message = "id value is not a string. Dropping event.",
+                        rejected_ids_counter = 1
+                    );
+                    return None;
+                }
+            }
+        }
+
+        let val = values
+            .iter()
+            .map(|val| val.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(separator.as_str());
+        let val = affixes.wrap(val, event)?;
+
+        doc.as_object_mut()
+            .unwrap()
+            .insert("_id".into(), json!(val));
+    }
+
+    Some(())
+}
+
+/// The hex SHA-1 of the event's fields. Objects serialize with sorted keys,
+/// so the same fields always hash the same.
+fn hash_id(event: &Event) -> String {
+    let document = serde_json::to_value(&event.as_log().clone().unflatten())
+        .and_then(|document| serde_json::to_vec(&document))
+        .unwrap();
+    openssl::sha::sha1(&document)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,1403 @@
+mod config;
+mod encoder;
+mod retry;
+mod service;
+#[cfg(test)]
+mod tests;
+
+pub use self::config::ElasticSearchConfig;
+use self::{
+    config::{
+        authorization, ApiVersion, BulkAction, Codec, CustomDocTypePolicy, DeadLetterSinkConfig,
+        ElasticSearchMode, HealthStatus, HealthcheckLevel, IdKey, InFlightLimit,
+        SchemaViolationPolicy,
+    },
+    encoder::{DebugTee, Encoder, FieldTypes, IdSource, SchemaCheck, Sequence},
+    retry::{retry_ambiguous_failures, ElasticSearchRetryLogic},
+    service::{
+        request_id, BatchDumper, CoalesceIdentical, Coalescing, CoerceMappingErrors,
+        CreateMissingIndices, DeadLetters, DocumentSamples, DumpFailedBatches,
+        IndexCreationThrottle, RecordTook, ReportFailedItems, ShadowSink, SkipEmptyBatches,
+        DEFAULT_COALESCE_COUNT_FIELD,
+    },
+};
+use crate::{
+    buffers::Acker,
+    event::{Event, Metric},
+    sinks::{
+        tcp::load_certificate,
+        util::{
+            buffer::GzipFlaggedBuffer,
+            http::{HostTlsConnector, HttpService, HttpServiceBuilder},
+            json_schema::JsonSchema,
+            retries::{FixedRetryPolicy, RetryBudget},
+            shared::{self, AckMembers, MemberBuffer, MemberEvents, SharedQueues},
+            state::{self, TrackBuffered, TrackRequests},
+            BatchServiceSink, BoundaryBuffer, Buffer, Compression, CountedBuffer,
+            DistinctKeysBuffer, PartitionBuffer, PartitionInnerBuffer, PostShutdown,
+            PostShutdownPolicy, SinkExt,
+        },
+    },
+    sources::internal_events,
+    template::{parse_timezone, Template},
+    topology::config::{DataType, SinkConfig},
+};
+use chrono::Utc;
+use futures::{stream::iter_ok, Future, Sink, Stream};
+use http::{
+    header::{HeaderName, HeaderValue},
+    Method, Uri,
+};
+use hyper::{Body, Request};
+use lazy_static::lazy_static;
+use rand::{rngs::SmallRng, FromEntropy};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{atomic::AtomicBool, Arc};
+use std::time::Duration;
+use tokio::util::FutureExt;
+use tower::ServiceBuilder;
+
+#[typetag::serde(name = "elasticsearch")]
+impl SinkConfig for ElasticSearchConfig {
+    fn build(&self, acker: Acker) -> Result<(super::RouterSink, super::Healthcheck), String> {
+        if self.log_resolved_config.unwrap_or(false) {
+            let resolved = serde_json::to_string_pretty(&self.resolved().redacted())
+                .map_err(|error| format!("Could not serialize resolved config: {}", error))?;
+            info!(message = "resolved elasticsearch config.", config = %resolved);
+        }
+
+        let service_builder = http_service_builder(self)?;
+        let (sink, dlq_healthcheck) = es(self, acker, &service_builder)?;
+        let healthcheck = healthchecks(self, &service_builder)?();
+        let healthcheck = match dlq_healthcheck {
+            Some(dlq_healthcheck) => join_dlq_healthcheck(healthcheck, dlq_healthcheck),
+            None => healthcheck,
+        };
+
+        Ok((sink, healthcheck))
+    }
+
+    fn healthcheck_factory(&self) -> Option<super::HealthcheckFactory> {
+        let service_builder = http_service_builder(self).ok()?;
+        let healthchecks = healthchecks(self, &service_builder).ok()?;
+        match &self.dlq_sink {
+            // The retried healthcheck has to cover the dead letter sink too,
+            // so it's only retried if that sink's can be.
+            Some(DeadLetterSinkConfig(dlq)) => {
+                let dlq_healthchecks = dlq.healthcheck_factory()?;
+                Some(Box::new(move || {
+                    join_dlq_healthcheck(healthchecks(), dlq_healthchecks())
+                }))
+            }
+            None => Some(healthchecks),
+        }
+    }
+
+    fn input_type(&self) -> DataType {
+        if self.metrics_as_logs.unwrap_or(false) {
+            DataType::Any
+        } else {
+            DataType::Log
+        }
+    }
+}
+
+fn http_service_builder(config: &ElasticSearchConfig) -> Result<HttpServiceBuilder, String> {
+    let mut service_builder = HttpService::builder();
+    service_builder.follow_redirects(config.follow_redirects.unwrap_or(false));
+    if config.detailed_timing.unwrap_or(false) {
+        service_builder.detailed_timing("elasticsearch");
+    }
+    if let Some(ttl) = config.dns_last_good_ttl_secs {
+        service_builder.dns_last_good_ttl(Duration::from_secs(ttl));
+    }
+    for (host, tls) in config.tls_hosts.iter().flatten() {
+        if tls.verify_certificate == Some(false) {
+            warn!(
+                message = "TLS certificate verification is disabled for host.",
+                %host
+            );
+        }
+        service_builder.host_tls(host.clone(), tls.build()?);
+    }
+    if let Some(tls) = &config.tls {
+        if tls.verify_certificate == Some(false) {
+            warn!(message = "TLS certificate verification is disabled.");
+            service_builder.verify_certificate(false);
+        }
+        if let Some(sni) = &tls.sni {
+            service_builder.tls_server_name(sni.clone());
+        }
+        if let Some(identity) = tls.identity()? {
+            service_builder.tls_identity(identity);
+        }
+        if let Some(path) = &tls.ca_path {
+            service_builder.tls_ca(load_certificate(path)?);
+        }
+    }
+
+    Ok(service_builder)
+}
+
+fn es(
+    config: &ElasticSearchConfig,
+    acker: Acker,
+    service_builder: &HttpServiceBuilder,
+) -> Result<(super::RouterSink, Option<super::Healthcheck>), String> {
+    let gzip = match config.compression.unwrap_or(Compression::Gzip) {
+        Compression::None => false,
+        Compression::Gzip => true,
+    };
+    // Every partition has its own buffer, so each request makes its own
+    // decision on whether it's big enough to be worth compressing.
+    let gzip_threshold = config.compression_threshold_bytes;
+    let gzip_level = config.compression_level;
+    match gzip_level {
+        Some(level) if level > 9 => {
+            return Err(format!(
+                "compression_level must be between 0 and 9, got {}",
+                level
+            ));
+        }
+        Some(_) if !gzip => {
+            return Err("compression_level requires compression = \"gzip\"".into());
+        }
+        _ => {}
+    }
+    let new_buffer = move || {
+        let buffer = match gzip_threshold {
+            Some(threshold) if gzip => Buffer::with_gzip_threshold(threshold),
+            _ => Buffer::new(gzip),
+        };
+        GzipFlaggedBuffer::new(match gzip_level {
+            Some(level) => buffer.gzip_level(level),
+            None => buffer,
+        })
+    };
+
+    let batch_size = config.batch_size.unwrap_or(bytesize::mib(10u64) as usize);
+    let batch_timeout = config.batch_timeout.unwrap_or(1);
+
+    let timeout = config.request_timeout_secs.unwrap_or(60);
+    let in_flight_limit = config
+        .request_in_flight_limit
+        .map_or(5, InFlightLimit::resolve);
+    let rate_limit_duration = config.request_rate_limit_duration_secs.unwrap_or(1);
+    let rate_limit_num = config.request_rate_limit_num.unwrap_or(5);
+    let retry_attempts = config.request_retry_attempts.unwrap_or(usize::max_value());
+    let retry_backoff_secs = config.request_retry_backoff_secs.unwrap_or(1);
+    let retry_max_backoff_secs = config.request_retry_max_backoff_secs.unwrap_or(30);
+    if retry_max_backoff_secs < retry_backoff_secs {
+        return Err(format!(
+            "request_retry_max_backoff_secs ({}) must be at least request_retry_backoff_secs ({})",
+            retry_max_backoff_secs, retry_backoff_secs
+        ));
+    }
+    let retry_in_flight_fraction = config.request_retry_in_flight_fraction.unwrap_or(1.0);
+    let codec = config
+        .encoding
+        .as_ref()
+        .and_then(|encoding| encoding.codec)
+        .unwrap_or(Codec::Json);
+    codec.check_bulk_support()?;
+
+    let (dead_letters, dlq_healthcheck) = match &config.dlq_sink {
+        Some(DeadLetterSinkConfig(dlq)) => {
+            // The dead letter sink's events never came from a buffer, so
+            // there's nothing to ack.
+            let (dlq, healthcheck) = dlq.build(Acker::Null)?;
+            (Some(Arc::new(DeadLetters::new(dlq))), Some(healthcheck))
+        }
+        None => (None, None),
+    };
+
+    let mut encoder = Encoder::new(config);
+    let timezone = config
+        .timezone
+        .as_ref()
+        .map(|tz| parse_timezone(tz))
+        .transpose()?;
+    if let Some(timezone) = timezone {
+        encoder = encoder.with_timezone(timezone);
+    }
+    if let Some(path) = &config.schema {
+        let violations = config
+            .schema_violations
+            .unwrap_or(SchemaViolationPolicy::Drop);
+        if violations == SchemaViolationPolicy::Dlq && dead_letters.is_none() {
+            return Err("schema_violations = \"dlq\" requires a dlq_sink".into());
+        }
+        encoder.schema = Some(SchemaCheck {
+            schema: Arc::new(JsonSchema::load(path)?),
+            violations,
+            dead_letters: dead_letters.clone(),
+        });
+    }
+
+    if let Some(types) = &config.field_types {
+        let violations = config
+            .field_type_violations
+            .unwrap_or(SchemaViolationPolicy::Drop);
+        if violations == SchemaViolationPolicy::Dlq && dead_letters.is_none() {
+            return Err("field_type_violations = \"dlq\" requires a dlq_sink".into());
+        }
+        encoder.field_types = Some(FieldTypes {
+            types: types.clone(),
+            violations,
+            dead_letters: dead_letters.clone(),
+        });
+    }
+
+    if let Some(fallback) = &config.index_fallback {
+        if fallback.contains("{{") || fallback.contains('%') {
+            return Err(format!(
+                "index_fallback must be a plain index name without templating, got {:?}",
+                fallback
+            ));
+        }
+    }
+
+    if config.warm_index.is_some() != config.warm_after_secs.is_some() {
+        return Err("warm_index and warm_after_secs must be set together".into());
+    }
+
+    if config.pipeline_map.is_some() && config.pipeline_key.is_none() {
+        return Err("pipeline_map requires a pipeline_key".into());
+    }
+
+    if let Some(IdKey::Composite(fields)) = &config.id_key {
+        if fields.is_empty() {
+            return Err("id_key must name at least one field".into());
+        }
+    }
+
+    match config.bulk_action {
+        // Identical events are created once.
+        Some(BulkAction::Create) if IdSource::new(config) == IdSource::Hash => {}
+        Some(BulkAction::Create) | Some(BulkAction::Update) if config.id_key.is_none() => {
+            return Err(format!(
+                "bulk_action = \"{}\" requires an id_key to identify documents",
+                config.bulk_action.unwrap().as_str()
+            ));
+        }
+        Some(BulkAction::Index) | Some(BulkAction::Update)
+            if config.mode == Some(ElasticSearchMode::DataStream) =>
+        {
+            return Err("data streams only accept bulk_action = \"create\"".into());
+        }
+        _ => {}
+    }
+
+    if config.version_key.is_none()
+        && (config.version_type.is_some() || config.version_type_key.is_some())
+    {
+        return Err("version_type and version_type_key require a version_key".into());
+    }
+
+    let debug_tee = match config.debug_tee_rate {
+        Some(rate) if !(rate >= 0.0 && rate <= 1.0) => {
+            return Err(format!(
+                "debug_tee_rate must be between 0 and 1, got {}",
+                rate
+            ));
+        }
+        Some(rate) if rate > 0.0 => Some(Arc::new(DebugTee::new(
+            rate,
+            config.debug_tee_redact_fields.clone().unwrap_or_default(),
+            SmallRng::from_entropy(),
+        ))),
+        _ => None,
+    };
+
+    if config.shared_batch.is_some() && config.tenant_key.is_some() {
+        return Err("shared_batch can't be used with tenant_key".into());
+    }
+
+    if let Some(field) = &config.add_sequence_field {
+        let sequence = match &config.sequence_state_path {
+            Some(path) => Sequence::persisted(field, path)?,
+            None => Sequence::new(field),
+        };
+        encoder.sequence = Some(Arc::new(sequence));
+    }
+
+    let auto_create_index = config.auto_create_index.unwrap_or(false);
+    let index_mapping = match &config.auto_create_index_mapping {
+        Some(path) => {
+            let mapping = std::fs::read(path)
+                .map_err(|error| format!("Could not read index mapping {:?}: {}", path, error))?;
+            serde_json::from_slice::<serde_json::Value>(&mapping)
+                .map_err(|error| format!("Could not parse index mapping {:?}: {}", path, error))?;
+            mapping
+        }
+        None => Vec::new(),
+    };
+
+    if !(retry_in_flight_fraction > 0.0 && retry_in_flight_fraction <= 1.0) {
+        return Err(format!(
+            "request_retry_in_flight_fraction must be greater than 0 and at most 1, got {}",
+            retry_in_flight_fraction
+        ));
+    }
+    let retry_budget = (in_flight_limit as f64 * retry_in_flight_fraction).ceil() as usize;
+    let retry_budget = RetryBudget::new(std::cmp::max(retry_budget, 1));
+
+    let policy = FixedRetryPolicy::new(
+        retry_attempts,
+        Duration::from_secs(retry_backoff_secs),
+        ElasticSearchRetryLogic {
+            invalid_response_attempts: config.request_retry_invalid_response_attempts.unwrap_or(3),
+            auto_create_index,
+            retry_ambiguous_failures: retry_ambiguous_failures(config),
+            rejected_execution_backoff: Duration::from_secs(
+                config
+                    .request_retry_rejected_execution_backoff_secs
+                    .unwrap_or(retry_max_backoff_secs),
+            ),
+            skipped_ambiguous_warned: Default::default(),
+        },
+    )
+    .with_budget(retry_budget.clone())
+    .with_exponential_backoff(Duration::from_secs(retry_max_backoff_secs))
+    .with_jitter(config.request_retry_jitter.unwrap_or(false));
+
+    let authorization = authorization(config)?;
+    let headers = config
+        .headers
+        .as_ref()
+        .unwrap_or(&HashMap::default())
+        .clone();
+    validate_headers(&headers)?;
+    if let Some(header) = &config.request_id_header {
+        HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| format!("Invalid request_id_header name {:?}", header))?;
+    }
+
+    let query = bulk_query(config);
+    let host = config.host.clone();
+    validate_host(&host)?;
+    bulk_uri(&host, &query, None)?;
+    let tenant_param = config
+        .tenant_query_param
+        .clone()
+        .unwrap_or_else(|| "tenant".into());
+
+    let index_creator = if auto_create_index {
+        let host = host.clone();
+        let authorization = authorization.clone();
+        let headers = headers.clone();
+        Some(service_builder.build(move |index: String| {
+            let mut builder = hyper::Request::builder();
+            builder.method(Method::PUT);
+            builder.uri(endpoint(&host, &index));
+
+            if !index_mapping.is_empty() {
+                builder.header("Content-Type", "application/json");
+            }
+            if let Some(ref auth) = authorization {
+                builder.header("Authorization", &auth[..]);
+            }
+            for (header, value) in &headers {
+                builder.header(&header[..], &value[..]);
+            }
+
+            builder.body(index_mapping.clone()).unwrap()
+        }))
+    } else {
+        None
+    };
+
+    let request_id_header = config.request_id_header.clone();
+    let emit_internal_events = config.emit_internal_events.unwrap_or(false);
+    let http_service = service_builder.build(move |request: BulkRequest| {
+        let request_id = request_id_header
+            .as_ref()
+            .map(|header| (header, request_id(&request)));
+        let ((body, gzip), tenant) = request.into_parts();
+        let tenant = tenant
+            .as_ref()
+            .map(|tenant| (&tenant_param[..], &tenant[..]));
+
+        let mut builder = hyper::Request::builder();
+        builder.method(Method::POST);
+        builder.uri(bulk_uri(&host, &query, tenant).expect("Invalid elasticsearch host"));
+
+        builder.header("Content-Type", codec.content_type());
+        if let Some(ref auth) = authorization {
+            builder.header("Authorization", &auth[..]);
+        }
+        for (header, value) in &headers {
+            builder.header(&header[..], &value[..]);
+        }
+
+        if gzip {
+            builder.header("Content-Encoding", "gzip");
+        }
+        if let Some((header, request_id)) = request_id {
+            debug!(message = "sending bulk request.", %request_id);
+            builder.header(&header[..], &request_id[..]);
+        }
+
+        if emit_internal_events {
+            internal_events::emit(counter("bytes_sent_total", body.len() as f64));
+        }
+        builder.body(body).unwrap()
+    });
+
+    // Each tenant needs its own request, so events are batched per tenant.
+    // Every distinct tenant value holds an open batch of its own, which adds
+    // up quickly for high cardinality fields.
+    let partitioned = config.tenant_key.is_some();
+    let min_batch_events = config.min_batch_events.unwrap_or(0);
+    let max_batch_age_secs = config.max_batch_age_secs.unwrap_or(60);
+    if min_batch_events > 0 && partitioned {
+        return Err("min_batch_events can't be used with tenant_key".into());
+    }
+    if min_batch_events > 0 && max_batch_age_secs < batch_timeout {
+        return Err(format!(
+            "max_batch_age_secs ({}) must be at least batch_timeout ({})",
+            max_batch_age_secs, batch_timeout
+        ));
+    }
+    let max_batch_age = Duration::from_secs(max_batch_age_secs);
+    let batch_timeout = Duration::from_secs(batch_timeout);
+    let aligned_flush = config.aligned_flush.unwrap_or(false);
+
+    // The shadow index shares the connection pool but skips the retry and
+    // rate limit layers and never acks, so it can't hold up the primary path.
+    let shadow = config
+        .shadow_index
+        .as_ref()
+        .map(|shadow_index| -> super::RouterSink {
+            let index = Template::from(shadow_index.as_str());
+            let encoder = Encoder {
+                index: match timezone {
+                    Some(timezone) => index.with_timezone(timezone),
+                    None => index,
+                },
+                ..encoder.clone()
+            };
+
+            let service = ServiceBuilder::new()
+                .concurrency_limit(in_flight_limit)
+                .timeout(Duration::from_secs(timeout))
+                .service(http_service.clone());
+            let sink = SkipEmptyBatches::new(BatchServiceSink::new(service, Acker::Null));
+            let batch = PartitionBuffer::new(new_buffer());
+            let encode = move |e: Event| {
+                let tenant = encoder.tenant(&e);
+                iter_ok(
+                    encoder
+                        .encode_event(e)
+                        .map(|body| PartitionInnerBuffer::new(body, tenant)),
+                )
+            };
+
+            if partitioned {
+                Box::new(
+                    sink.partitioned_batched_with_min(batch, batch_size, batch_timeout)
+                        .aligned_linger(aligned_flush)
+                        .with_flat_map(encode),
+                )
+            } else {
+                Box::new(
+                    sink.batched_with_min(batch, batch_size, batch_timeout)
+                        .aligned_linger(aligned_flush)
+                        .with_flat_map(encode),
+                )
+            }
+        });
+
+    let dumper = config.dump_failed_batches_to.as_ref().map(|dir| {
+        BatchDumper::new(
+            dir.clone(),
+            config.dump_failed_batches_max_files.unwrap_or(10),
+        )
+    });
+
+    let service = ServiceBuilder::new()
+        .concurrency_limit(in_flight_limit)
+        .rate_limit(rate_limit_num, Duration::from_secs(rate_limit_duration))
+        .retry(policy)
+        .timeout(Duration::from_secs(timeout))
+        .service(CoerceMappingErrors::new(
+            CreateMissingIndices::new(RecordTook::new(http_service), index_creator),
+            config.coerce_on_mapping_error.unwrap_or(false),
+            dead_letters.clone(),
+        ));
+    let service = DumpFailedBatches::new(service, dumper, dead_letters)
+        .ignore_version_conflicts(config.ignore_version_conflicts.unwrap_or(false))
+        .coerces_mapping_errors(config.coerce_on_mapping_error.unwrap_or(false));
+    let service = ReportFailedItems::new(
+        service,
+        config.ignore_version_conflicts.unwrap_or(false),
+        config.failed_document_sample_bytes.map(|max_bytes| {
+            DocumentSamples::new(
+                max_bytes,
+                config
+                    .failed_document_redact_fields
+                    .as_ref()
+                    .map_or(&[][..], Vec::as_slice),
+            )
+        }),
+    )
+    .emit_internal_events(config.emit_internal_events.unwrap_or(false))
+    .log_request_ids(config.request_id_header.is_some());
+
+    let sink_state = state::publish();
+    sink_state.update(|state| state.concurrency = in_flight_limit);
+    sink_state.track_retry_budget(retry_budget);
+    let service = TrackRequests::new(service, sink_state.clone());
+
+    let flush_on_field = config.flush_on_field.clone();
+    let flush_on_distinct_keys = config.flush_on_distinct_keys.clone();
+    if flush_on_distinct_keys
+        .as_ref()
+        .map_or(false, |flush_on| flush_on.threshold == 0)
+    {
+        return Err("flush_on_distinct_keys.threshold must be at least 1".into());
+    }
+    let max_distinct_keys = flush_on_distinct_keys
+        .as_ref()
+        .map(|flush_on| flush_on.threshold);
+    let encoder_for_throttle = encoder.clone();
+    // The body of an event, whether it ends its batch, the key counted
+    // towards its batch's distinct keys, and its tenant.
+    let encode = move |e: Event| {
+        let boundary = flush_on_field
+            .as_ref()
+            .map(|flush_on| flush_on.matches(&e))
+            .unwrap_or(false);
+        let key = flush_on_distinct_keys
+            .as_ref()
+            .and_then(|flush_on| flush_on.key(&e));
+        let tenant = encoder.tenant(&e);
+        let body = encoder.encode_event(e)?;
+        if let Some(tee) = &debug_tee {
+            if let Some(line) = tee.sample(&body) {
+                // Printed rather than logged, so it's there whatever the log
+                // level. A closed stdout shouldn't stop the sink.
+                let _ = writeln!(std::io::stdout(), "{}", line);
+            }
+        }
+        Some((body, boundary, key, tenant))
+    };
+
+    let coalescing = if config.coalesce_identical.unwrap_or(false) {
+        Some(Coalescing {
+            keys: config
+                .coalesce_keys
+                .as_ref()
+                .map(|keys| keys.iter().map(|key| key.as_str().into()).collect()),
+            count_field: config
+                .coalesce_count_field
+                .as_ref()
+                .map_or(DEFAULT_COALESCE_COUNT_FIELD, String::as_str)
+                .into(),
+            uncoalesced_warned: AtomicBool::new(false),
+        })
+    } else {
+        None
+    };
+    let sink: super::RouterSink = if let Some(name) = &config.shared_batch {
+        // Only the first sink to join the queue builds its pipeline, so sinks
+        // that would build a different one never share a queue, and they all
+        // report the state of the one pipeline.
+        let key = shared_queue_key(name, config);
+        let member = SHARED_BATCHES.join(&key, acker, sink_state, move |members, rx| {
+            let batch = MemberBuffer::new(PartitionBuffer::new(DistinctKeysBuffer::new(
+                BoundaryBuffer::new(new_buffer()),
+                max_distinct_keys,
+            )));
+            let sink = BatchServiceSink::new(AckMembers::new(service, members), Acker::Null);
+            let sink = SkipEmptyBatches::new(sink)
+                .batched_with_min(batch, batch_size, batch_timeout)
+                .aligned_linger(aligned_flush)
+                .min_items(min_batch_events, max_batch_age);
+            shared::forward(rx, sink)
+        });
+        state::republish(member.state().clone());
+        let member_id = member.member();
+        let encode = move |(e, count): (Event, usize)| {
+            iter_ok(encode(e).map(|(body, boundary, key, _)| {
+                let events = MemberEvents {
+                    member: member_id,
+                    count,
+                };
+                (
+                    PartitionInnerBuffer::new(((body, boundary), key), None),
+                    events,
+                )
+            }))
+        };
+        Box::new(CoalesceIdentical::new(
+            member.with_flat_map(encode),
+            coalescing,
+        ))
+    } else {
+        let sink = TrackBuffered::new(
+            SkipEmptyBatches::new(BatchServiceSink::new(service, acker)),
+            sink_state.clone(),
+        );
+        let batch = PartitionBuffer::new(CountedBuffer::new(DistinctKeysBuffer::new(
+            BoundaryBuffer::new(new_buffer()),
+            max_distinct_keys,
+        )));
+        let encode = move |(e, count): (Event, usize)| {
+            let encoded = encode(e);
+            if encoded.is_some() {
+                sink_state.update(|state| state.buffered_events += count);
+            }
+            iter_ok(encoded.map(|(body, boundary, key, tenant)| {
+                PartitionInnerBuffer::new((((body, boundary), key), count), tenant)
+            }))
+        };
+        if partitioned {
+            Box::new(CoalesceIdentical::new(
+                sink.partitioned_batched_with_min(batch, batch_size, batch_timeout)
+                    .aligned_linger(aligned_flush)
+                    .with_flat_map(encode),
+                coalescing,
+            ))
+        } else {
+            Box::new(CoalesceIdentical::new(
+                sink.batched_with_min(batch, batch_size, batch_timeout)
+                    .aligned_linger(aligned_flush)
+                    .min_items(min_batch_events, max_batch_age)
+                    .with_flat_map(encode),
+                coalescing,
+            ))
+        }
+    };
+    let sink: super::RouterSink = match config.index_creation_rate_limit_num {
+        Some(limit) => Box::new(IndexCreationThrottle::new(
+            sink,
+            encoder_for_throttle,
+            limit,
+            Duration::from_secs(config.index_creation_rate_limit_duration_secs.unwrap_or(1)),
+        )),
+        None => sink,
+    };
+    let sink = PostShutdown::new(
+        sink,
+        config
+            .post_shutdown_events
+            .unwrap_or(PostShutdownPolicy::AcceptUntilFlush),
+    );
+
+    let sink: super::RouterSink = match shadow {
+        Some(shadow) => Box::new(ShadowSink::new(sink, shadow)),
+        None => Box::new(sink),
+    };
+    Ok((sink, dlq_healthcheck))
+}
+
+/// A finished batch and whether it was gzipped, along with the tenant its
+/// events belong to, if any.
+type BulkRequest = PartitionInnerBuffer<(Vec<u8>, bool), Option<String>>;
+
+/// An encoded event sent to a shared batch, along with the sink it came from.
+type SharedBulkItem = (
+    PartitionInnerBuffer<(Vec<u8>, bool), Option<String>>,
+    MemberEvents,
+);
+
+lazy_static! {
+    static ref SHARED_BATCHES: SharedQueues<SharedBulkItem> = SharedQueues::default();
+}
+
+// The options that only change how a sink encodes its own events, or wrap the
+// sink it hands its events to, so sinks sharing a batch queue may differ in
+// them. Every other option is part of the pipeline the queue's first sink
+// builds.
+const SHARED_MEMBER_OPTIONS: &[&str] = &[
+    "index",
+    "index_fallback",
+    "timezone",
+    "metrics_as_logs",
+    "metrics_index",
+    "level_routing",
+    "level_key",
+    "warm_index",
+    "warm_after_secs",
+    "pipeline_key",
+    "pipeline_map",
+    "default_pipeline",
+    "raw_action_key",
+    "doc_type",
+    "custom_doc_type",
+    "id_key",
+    "id_key_separator",
+    "id_strategy",
+    "remove_id_field",
+    "id_prefix",
+    "id_suffix",
+    "non_string_id",
+    "version_key",
+    "version_type",
+    "version_type_key",
+    "coalesce_identical",
+    "coalesce_keys",
+    "coalesce_count_field",
+    "index_creation_rate_limit_num",
+    "index_creation_rate_limit_duration_secs",
+    "shadow_index",
+    "index_denylist",
+    "normalize_field_names",
+    "max_fields_per_document",
+    "extra_fields",
+    "numeric_types",
+    "field_types",
+    "field_type_violations",
+    "truncate_fields",
+    "default_fields",
+    "default_fields_precedence",
+    "metadata_fields",
+    "future_timestamps",
+    "future_timestamp_tolerance_secs",
+    "flattened_fields",
+    "max_array_depth",
+    "max_line_bytes",
+    "oversized_lines",
+    "array_nesting",
+    "schema",
+    "schema_violations",
+    "add_sequence_field",
+    "sequence_state_path",
+    "post_shutdown_events",
+    "log_resolved_config",
+    "log_capabilities",
+    "debug_tee_rate",
+    "debug_tee_redact_fields",
+    "healthcheck_level",
+    "healthcheck_timeout_secs",
+    "healthcheck_min_status",
+    "shared_batch",
+];
+
+/// The key of the shared queue a sink joins: its `shared_batch` along with
+/// every option of the pipeline the queue's first sink builds, so that sinks
+/// only share a queue when the pipeline would be the same for all of them.
+fn shared_queue_key(name: &str, config: &ElasticSearchConfig) -> String {
+    let mut config = serde_json::to_value(config).expect("configs serialize");
+    if let Some(options) = config.as_object_mut() {
+        for option in SHARED_MEMBER_OPTIONS {
+            options.remove(*option);
+        }
+    }
+    format!("{}/{}", name, config)
+}
+
+/// The query string of every bulk request: the configured `query` and the
+/// bulk-wide `pipeline`, unless `query` already picks one.
+fn bulk_query(config: &ElasticSearchConfig) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(ref params) = config.query {
+        for (p, v) in params {
+            query.append_pair(&p[..], &v[..]);
+        }
+    }
+    if let Some(pipeline) = &config.pipeline {
+        let overridden = config
+            .query
+            .as_ref()
+            .map_or(false, |params| params.contains_key("pipeline"));
+        if !overridden {
+            query.append_pair("pipeline", pipeline);
+        }
+    }
+    query.finish()
+}
+
+fn bulk_uri(host: &str, query: &str, tenant: Option<(&str, &str)>) -> Result<Uri, String> {
+    let mut query = url::form_urlencoded::Serializer::new(query.to_string());
+    if let Some((param, tenant)) = tenant {
+        query.append_pair(param, tenant);
+    }
+    let query = query.finish();
+
+    let uri = if query.is_empty() {
+        endpoint(host, "_bulk")
+    } else {
+        format!("{}?{}", endpoint(host, "_bulk"), query)
+    };
+    uri.parse::<Uri>().map_err(|err| err.to_string())
+}
+
+/// Checks the custom headers up front, as an invalid one would otherwise fail
+/// every request. Values are left out of the error, since they often hold
+/// credentials.
+fn validate_headers(headers: &HashMap<String, String>) -> Result<(), String> {
+    for (name, value) in headers {
+        HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name {:?}", name))?;
+        HeaderValue::from_str(value).map_err(|_| format!("Invalid value for header {:?}", name))?;
+    }
+    Ok(())
+}
+
+/// Checks that the host is an absolute `http` or `https` URI, so that a typo
+/// fails the config rather than every request.
+fn validate_host(host: &str) -> Result<(), String> {
+    let invalid = |reason: &str| format!("Invalid host {:?}: {}", host, reason);
+    let uri = host
+        .parse::<Uri>()
+        .map_err(|error| invalid(&error.to_string()))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        _ => return Err(invalid("must start with http:// or https://")),
+    }
+
+    let authority = uri
+        .authority_part()
+        .map_or("", |authority| authority.as_str());
+    let host_port = authority.rsplit('@').next().unwrap_or("");
+    if host_port.is_empty() {
+        return Err(invalid("missing host name"));
+    }
+    // The brackets of an IPv6 address hold colons of their own.
+    let port = host_port
+        .rfind(':')
+        .filter(|at| !host_port[*at..].contains(']'))
+        .map(|at| &host_port[at + 1..]);
+    if let Some(port) = port {
+        if port.parse::<u16>().is_err() {
+            return Err(invalid(&format!("invalid port {:?}", port)));
+        }
+    }
+    Ok(())
+}
+
+/// Joins an API path onto the host, keeping any path prefix the host has
+/// (e.g. when Elasticsearch is behind a reverse proxy).
+fn endpoint(host: &str, path: &str) -> String {
+    format!("{}/{}", host.trim_end_matches('/'), path)
+}
+
+/// An internal event for a bulk request that failed for good.
+/// A counter for `internal_events` sources, tagged with the component it's
+/// about like failure events are.
+fn counter(name: &str, val: f64) -> Event {
+    let tags = vec![("component_type".to_string(), "elasticsearch".to_string())];
+    Event::Metric(Metric::Counter {
+        name: name.into(),
+        val,
+        timestamp: Some(Utc::now()),
+        tags: Some(tags.into_iter().collect()),
+    })
+}
+
+fn health_uri(host: &str, level: HealthcheckLevel) -> String {
+    endpoint(host, &format!("_cluster/health?level={}", level.as_str()))
+}
+
+/// The data streams matching the index, with any part rendered per event
+/// matching anything, since there's no event to render it with.
+fn data_stream_uri(host: &str, index: &str) -> String {
+    lazy_static! {
+        static ref DYNAMIC: Regex = Regex::new(r"\{\{[^}]+\}\}|%.").unwrap();
+    }
+    let pattern = DYNAMIC.replace_all(index, "*");
+    endpoint(host, &format!("_data_stream/{}", pattern))
+}
+
+/// The healthcheck request, authenticated and with the custom headers of the
+/// bulk requests, so that it passes wherever they do.
+fn healthcheck_request(
+    uri: String,
+    authorization: Option<&str>,
+    headers: Option<&HashMap<String, String>>,
+) -> Request<Body> {
+    let mut builder = Request::get(uri);
+    if let Some(authorization) = authorization {
+        builder.header("Authorization", authorization);
+    }
+    for (header, value) in headers.into_iter().flatten() {
+        builder.header(&header[..], &value[..]);
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+/// Makes the sink's healthcheck, on a client of its own, anew on every call.
+fn healthchecks(
+    config: &ElasticSearchConfig,
+    service_builder: &HttpServiceBuilder,
+) -> Result<super::HealthcheckFactory, String> {
+    // Only the cluster health API reports a status; data streams just have
+    // to exist.
+    let (uri, min_status) = match config.mode.unwrap_or(ElasticSearchMode::Bulk) {
+        ElasticSearchMode::Bulk => (
+            health_uri(
+                &config.host,
+                config
+                    .healthcheck_level
+                    .unwrap_or(HealthcheckLevel::Cluster),
+            ),
+            Some(
+                config
+                    .healthcheck_min_status
+                    .unwrap_or(HealthStatus::Yellow),
+            ),
+        ),
+        ElasticSearchMode::DataStream => (
+            data_stream_uri(
+                &config.host,
+                config
+                    .index
+                    .as_ref()
+                    .map_or("vector-%Y.%m.%d", String::as_str),
+            ),
+            None,
+        ),
+    };
+    let capabilities = if config.log_capabilities.unwrap_or(false) {
+        Some((endpoint(&config.host, ""), write_summary(config)))
+    } else {
+        None
+    };
+    let authorization = authorization(config)?;
+    let headers = config.headers.clone();
+    let timeout = Duration::from_secs(config.healthcheck_timeout_secs.unwrap_or(10));
+    let client = service_builder.client();
+
+    Ok(Box::new(move || {
+        let request = |uri: &str| {
+            healthcheck_request(
+                uri.into(),
+                authorization.as_ref().map(String::as_str),
+                headers.as_ref(),
+            )
+        };
+        let healthcheck = healthcheck(request(&uri), &client, min_status);
+        let healthcheck: super::Healthcheck = match &capabilities {
+            Some((info, writes)) => {
+                let capabilities = log_capabilities(request(info), &client, writes.clone());
+                Box::new(healthcheck.and_then(|()| capabilities))
+            }
+            None => healthcheck,
+        };
+        healthcheck_timeout(healthcheck, timeout)
+    }))
+}
+
+fn healthcheck(
+    request: Request<Body>,
+    client: &hyper::Client<HostTlsConnector>,
+    min_status: Option<HealthStatus>,
+) -> super::Healthcheck {
+    let healthcheck = client
+        .request(request)
+        .map_err(|err| err.to_string())
+        .and_then(|response| {
+            if response.status() == hyper::StatusCode::OK {
+                Ok(response)
+            } else {
+                Err(format!("Unexpected status: {}", response.status()))
+            }
+        })
+        .and_then(move |response| match min_status {
+            Some(min_status) => futures::future::Either::A(
+                response
+                    .into_body()
+                    .concat2()
+                    .map_err(|err| err.to_string())
+                    .and_then(move |body| check_health(&body, min_status)),
+            ),
+            None => futures::future::Either::B(futures::future::ok(())),
+        });
+
+    Box::new(healthcheck)
+}
+
+#[derive(Deserialize)]
+struct ClusterHealth {
+    status: HealthStatus,
+}
+
+/// Passes if the cluster health API answered with a status at least as good
+/// as `min_status`.
+fn check_health(body: &[u8], min_status: HealthStatus) -> Result<(), String> {
+    let health = serde_json::from_slice::<ClusterHealth>(body)
+        .map_err(|error| format!("Invalid cluster health: {}", error))?;
+    if health.status >= min_status {
+        Ok(())
+    } else {
+        Err(format!(
+            "Cluster health is {}, below healthcheck_min_status {}",
+            health.status.as_str(),
+            min_status.as_str()
+        ))
+    }
+}
+
+/// Passes once both the sink's own healthcheck and its dead letter sink's
+/// have, so a misconfigured `dlq_sink` is caught at startup too.
+fn join_dlq_healthcheck(
+    healthcheck: super::Healthcheck,
+    dlq_healthcheck: super::Healthcheck,
+) -> super::Healthcheck {
+    let dlq_healthcheck = dlq_healthcheck.map_err(|error| format!("dlq_sink: {}", error));
+    Box::new(healthcheck.join(dlq_healthcheck).map(|_| ()))
+}
+
+/// Fails the healthcheck once `timeout` passes, so a cluster that accepts
+/// connections but never answers can't hold up startup.
+fn healthcheck_timeout(healthcheck: super::Healthcheck, timeout: Duration) -> super::Healthcheck {
+    let healthcheck = healthcheck.timeout(timeout).map_err(move |error| {
+        if error.is_elapsed() {
+            format!("Healthcheck timed out after {} seconds", timeout.as_secs())
+        } else if let Some(error) = error.into_inner() {
+            error
+        } else {
+            "Healthcheck timer failed".into()
+        }
+    });
+
+    Box::new(healthcheck)
+}
+
+#[derive(Deserialize)]
+struct ClusterInfo {
+    version: ClusterVersion,
+}
+
+#[derive(Deserialize)]
+struct ClusterVersion {
+    number: String,
+}
+
+/// Asks the cluster which version it runs and logs it along with how the sink
+/// writes to it. Failing to find out doesn't fail the healthcheck.
+fn log_capabilities(
+    request: Request<Body>,
+    client: &hyper::Client<HostTlsConnector>,
+    writes: Vec<String>,
+) -> super::Healthcheck {
+    let capabilities = client
+        .request(request)
+        .and_then(|response| response.into_body().concat2())
+        .then(move |body| -> Result<(), String> {
+            match body
+                .map_err(|error| error.to_string())
+                .and_then(|body| capability_summary(&body, &writes))
+            {
+                Ok(summary) => info!(message = "detected Elasticsearch capabilities.", %summary),
+                Err(error) => warn!(
+                    message = "could not detect Elasticsearch capabilities.",
+                    %error
+                ),
+            }
+            Ok(())
+        });
+
+    Box::new(capabilities)
+}
+
+/// How this sink is configured to write, for the capability summary.
+fn write_summary(config: &ElasticSearchConfig) -> Vec<String> {
+    let mut summary = Vec::new();
+    if config.mode == Some(ElasticSearchMode::DataStream) {
+        summary.push("writing to data streams".to_string());
+    } else {
+        summary.push("writing to indices".to_string());
+        let doc_type = match config.custom_doc_type {
+            // See `doc_type`.
+            Some(CustomDocTypePolicy::Coerce) if config.api_version == Some(ApiVersion::V7) => {
+                "_doc"
+            }
+            _ => config.doc_type.as_ref().map_or("_doc", String::as_str),
+        };
+        summary.push(format!("sending _type \"{}\"", doc_type));
+    }
+    summary.push(match config.compression.unwrap_or(Compression::Gzip) {
+        Compression::Gzip => "gzip compression".into(),
+        Compression::None => "no compression".into(),
+    });
+    summary
+}
+
+/// A one line summary of what the cluster whose root endpoint answered with
+/// `info` supports and how this sink writes to it.
+fn capability_summary(info: &[u8], writes: &[String]) -> Result<String, String> {
+    let info = serde_json::from_slice::<ClusterInfo>(info)
+        .map_err(|error| format!("Invalid cluster info: {}", error))?;
+    let number = info.version.number;
+    let mut parts = number.split('.').map(|part| part.parse::<u32>().ok());
+    let version = (
+        parts.next().and_then(|part| part).unwrap_or(0),
+        parts.next().and_then(|part| part).unwrap_or(0),
+    );
+
+    let mut summary = writes.to_vec();
+    // Data streams came with 7.9.
+    summary.push(if version >= (7, 9) {
+        "data streams available".into()
+    } else {
+        "data streams unavailable".into()
+    });
+
+    Ok(format!(
+        "Elasticsearch {} detected: {}",
+        number,
+        summary.join(", ")
+    ))
+}
+
+#[cfg(test)]
+#[cfg(feature = "es-integration-tests")]
+mod integration_tests {
+    use super::*;
+    use crate::buffers::Acker;
+    use crate::{
+        event,
+        test_util::{block_on, random_events_with_stream, random_string},
+        topology::config::SinkConfig,
+        Event,
+    };
+    use elastic::client::SyncClientBuilder;
+    use futures::{Future, Sink, Stream};
+    use hyper::{Body, Client, Request};
+    use hyper_tls::HttpsConnector;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn structures_events_correctly() {
+        let index = gen_index();
+        let config = ElasticSearchConfig {
+            host: "http://localhost:9200/".into(),
+            index: Some(index.clone()),
+            doc_type: Some("log_lines".into()),
+            id_key: Some("my_id".into()),
+            compression: Some(Compression::None),
+            batch_size: Some(1),
+            ..Default::default()
+        };
+
+        let (sink, _hc) = config.build(Acker::Null).unwrap();
+
+        let mut input_event = Event::from("raw log line");
+        input_event
+            .as_mut_log()
+            .insert_explicit("my_id".into(), "42".into());
+        input_event
+            .as_mut_log()
+            .insert_explicit("foo".into(), "bar".into());
+
+        let pump = sink.send(input_event.clone());
+        block_on(pump).unwrap();
+
+        // make sure writes all all visible
+        block_on(flush(config.host)).unwrap();
+
+        let client = SyncClientBuilder::new().build().unwrap();
+
+        let response = client
+            .search::<Value>()
+            .index(index)
+            .body(json!({
+                "query": { "query_string": { "query": "*" } }
+            }))
+            .send()
+            .unwrap();
+        assert_eq!(1, response.total());
+
+        let hit = response.into_hits().next().unwrap();
+        assert_eq!("42", hit.id());
+
+        let value = hit.into_document().unwrap();
+        let expected = json!({
+            "message": "raw log line",
+            "my_id": "42",
+            "foo": "bar",
+            "timestamp": input_event.as_log()[&event::TIMESTAMP],
+        });
+        assert_eq!(expected, value);
+    }
+
+    #[test]
+    fn insert_events() {
+        let index = gen_index();
+        let config = ElasticSearchConfig {
+            host: "http://localhost:9200/".into(),
+            index: Some(index.clone()),
+            doc_type: Some("log_lines".into()),
+            compression: Some(Compression::None),
+            batch_size: Some(1),
+            ..Default::default()
+        };
+
+        let (sink, _hc) = config.build(Acker::Null).unwrap();
+
+        let (input, events) = random_events_with_stream(100, 100);
+
+        let pump = sink.send_all(events);
+        block_on(pump).unwrap();
+
+        // make sure writes all all visible
+        block_on(flush(config.host)).unwrap();
+
+        let client = SyncClientBuilder::new().build().unwrap();
+
+        let response = client
+            .search::<Value>()
+            .index(index)
+            .body(json!({
+                "query": { "query_string": { "query": "*" } }
+            }))
+            .send()
+            .unwrap();
+
+        assert_eq!(input.len() as u64, response.total());
+        let input = input
+            .into_iter()
+            .map(|rec| serde_json::to_value(rec.into_log().unflatten()).unwrap())
+            .collect::<Vec<_>>();
+        for hit in response.into_hits() {
+            let event = hit.into_document().unwrap();
+            assert!(input.contains(&event));
+        }
+    }
+
+    #[test]
+    fn creates_missing_indices() {
+        let prefix = format!("test-noauto-{}", random_string(10).to_lowercase());
+        let index = format!("{}-1", prefix);
+        block_on(put(
+            "http://localhost:9200/_cluster/settings",
+            json!({ "transient": { "action.auto_create_index": format!("-{}*,+*", prefix) } }),
+        ))
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mapping = dir.path().join("mapping.json");
+        std::fs::write(
+            &mapping,
+            json!({ "mappings": { "log_lines": { "properties": { "foo": { "type": "keyword" } } } } })
+                .to_string(),
+        )
+        .unwrap();
+
+        let config = ElasticSearchConfig {
+            host: "http://localhost:9200/".into(),
+            index: Some(index.clone()),
+            doc_type: Some("log_lines".into()),
+            compression: Some(Compression::None),
+            batch_size: Some(1),
+            request_retry_backoff_secs: Some(0),
+            ..Default::default()
+        };
+
+        // Without auto_create_index the batch is given up on.
+        let (sink, _hc) = config.build(Acker::Null).unwrap();
+        block_on(sink.send(Event::from("dropped"))).unwrap();
+        assert_eq!(
+            404,
+            block_on(status(format!("http://localhost:9200/{}", index))).unwrap()
+        );
+
+        let config = ElasticSearchConfig {
+            auto_create_index: Some(true),
+            auto_create_index_mapping: Some(mapping),
+            ..config
+        };
+        let (sink, _hc) = config.build(Acker::Null).unwrap();
+        let mut event = Event::from("created");
+        event
+            .as_mut_log()
+            .insert_explicit("foo".into(), "bar".into());
+        block_on(sink.send(event)).unwrap();
+        block_on(flush(config.host)).unwrap();
+
+        let client = SyncClientBuilder::new().build().unwrap();
+        let response = client
+            .search::<Value>()
+            .index(index.clone())
+            .body(json!({
+                "query": { "query_string": { "query": "*" } }
+            }))
+            .send()
+            .unwrap();
+        assert_eq!(1, response.total());
+
+        let mapping = block_on(get_json(format!(
+            "http://localhost:9200/{}/_mapping",
+            index
+        )))
+        .unwrap();
+        assert_eq!(
+            json!("keyword"),
+            mapping[&index]["mappings"]["log_lines"]["properties"]["foo"]["type"]
+        );
+
+        block_on(put(
+            "http://localhost:9200/_cluster/settings",
+            json!({ "transient": { "action.auto_create_index": null } }),
+        ))
+        .unwrap();
+    }
+
+    fn client() -> Client<HttpsConnector<hyper::client::HttpConnector>> {
+        let https = HttpsConnector::new(4).expect("TLS initialization failed");
+        Client::builder().build(https)
+    }
+
+    fn put(uri: &str, body: Value) -> impl Future<Item = (), Error = String> {
+        let request = Request::put(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        client()
+            .request(request)
+            .map_err(|err| err.to_string())
+            .and_then(|response| {
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("Unexpected status: {}", response.status()))
+                }
+            })
+    }
+
+    fn status(uri: String) -> impl Future<Item = u16, Error = String> {
+        let request = Request::head(uri).body(Body::empty()).unwrap();
+        client()
+            .request(request)
+            .map(|response| response.status().as_u16())
+            .map_err(|err| err.to_string())
+    }
+
+    fn get_json(uri: String) -> impl Future<Item = Value, Error = String> {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        client()
+            .request(request)
+            .and_then(|response| response.into_body().concat2())
+            .map_err(|err| err.to_string())
+            .and_then(|body| serde_json::from_slice(&body).map_err(|err| err.to_string()))
+    }
+
+    fn gen_index() -> String {
+        format!("test-{}", random_string(10).to_lowercase())
+    }
+
+    fn flush(host: String) -> impl Future<Item = (), Error = String> {
+        let uri = format!("{}/_flush", host);
+        let request = Request::post(uri).body(Body::empty()).unwrap();
+
+        let https = HttpsConnector::new(4).expect("TLS initialization failed");
+        let client = Client::builder().build(https);
+        client
+            .request(request)
+            .map_err(|err| err.to_string())
+            .and_then(|response| {
+                if response.status() == hyper::StatusCode::OK {
+                    Ok(())
+                } else {
+                    Err(format!("Unexpected status: {}", response.status()))
+                }
+            })
+    }
+
+}
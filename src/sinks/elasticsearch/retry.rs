@@ -0,0 +1,346 @@
+use super::{
+    config::{BulkAction, ElasticSearchConfig, ElasticSearchMode},
+    encoder::IdSource,
+};
+use crate::sinks::util::{http::HttpRetryLogic, retries::RetryLogic};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+/// Retries like any HTTP sink, but also retries successful responses whose
+/// body isn't a bulk response, which some proxies send under load without
+/// the batch having been indexed.
+#[derive(Clone)]
+pub(super) struct ElasticSearchRetryLogic {
+    pub(super) invalid_response_attempts: usize,
+    pub(super) auto_create_index: bool,
+    pub(super) retry_ambiguous_failures: bool,
+    // The least wait after Elasticsearch's bulk queue was full.
+    pub(super) rejected_execution_backoff: Duration,
+    // Shared by the clones made for every request, but not by other sinks.
+    pub(super) skipped_ambiguous_warned: Arc<AtomicBool>,
+}
+
+impl ElasticSearchRetryLogic {
+    /// Whether to retry after a failure the batch may have been written in
+    /// full or in part despite, which sends the written documents again.
+    fn retry_ambiguous(&self, failure: &str) -> bool {
+        if !self.retry_ambiguous_failures {
+            // Every such failure would warn otherwise, which adds up fast
+            // while the cluster is struggling.
+            if !self.skipped_ambiguous_warned.swap(true, Ordering::Relaxed) {
+                warn!(
+                    message = "not retrying batches that may have been written, as it could duplicate documents; set request_retry_ambiguous_failures to retry anyway. This is only logged once per sink.",
+                    %failure,
+                    skipped_ambiguous_retries_counter = 1
+                );
+            } else {
+                debug!(
+                    message = "not retrying a batch that may have been written.",
+                    %failure,
+                    skipped_ambiguous_retries_counter = 1
+                );
+            }
+        }
+        self.retry_ambiguous_failures
+    }
+}
+
+/// Whether to retry batches after failures they may have been written
+/// despite. By default they're only retried when retries can't duplicate
+/// documents, unless `request_retry_ambiguous_failures` is turned on.
+pub(super) fn retry_ambiguous_failures(config: &ElasticSearchConfig) -> bool {
+    config
+        .request_retry_ambiguous_failures
+        .unwrap_or_else(|| retries_are_idempotent(config))
+}
+
+/// Whether sending a batch again after it was written can't duplicate its
+/// documents: those with ids are overwritten, rejected as conflicts or
+/// updated in place, but those without ids are written anew.
+pub(super) fn retries_are_idempotent(config: &ElasticSearchConfig) -> bool {
+    if config.raw_action_key.is_some() {
+        // Pre-rendered actions may index documents without ids.
+        return false;
+    }
+    let bulk_action = if config.mode == Some(ElasticSearchMode::DataStream) {
+        BulkAction::Create
+    } else {
+        config.bulk_action.unwrap_or(BulkAction::Index)
+    };
+    match bulk_action {
+        BulkAction::Index | BulkAction::Create => IdSource::new(config) != IdSource::None,
+        BulkAction::Update => true,
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct BulkResponse {
+    #[allow(dead_code)]
+    pub(super) errors: bool,
+    #[allow(dead_code)]
+    pub(super) items: Vec<serde::de::IgnoredAny>,
+}
+
+pub(super) fn is_invalid_bulk_response(response: &hyper::Response<Bytes>) -> bool {
+    response.status().is_success()
+        && serde_json::from_slice::<BulkResponse>(response.body()).is_err()
+}
+
+#[derive(Deserialize)]
+pub(super) struct BulkItemsResponse {
+    pub(super) errors: bool,
+    pub(super) items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct BulkItem {
+    #[serde(rename = "_id")]
+    pub(super) id: Option<String>,
+    #[serde(rename = "_index")]
+    pub(super) index: Option<String>,
+    pub(super) error: Option<BulkItemError>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct BulkItemError {
+    #[serde(rename = "type")]
+    pub(super) kind: String,
+    pub(super) reason: Option<String>,
+    pub(super) index: Option<String>,
+}
+
+/// The indices items of a bulk response failed to be written to because they
+/// don't exist and the cluster doesn't create indices automatically.
+pub(super) fn missing_indices(response: &hyper::Response<Bytes>) -> Vec<String> {
+    let response = match serde_json::from_slice::<BulkItemsResponse>(response.body()) {
+        Ok(response) if response.errors => response,
+        _ => return Vec::new(),
+    };
+
+    let mut indices = response
+        .items
+        .into_iter()
+        .flat_map(|item| item.into_iter().map(|(_, item)| item))
+        .filter_map(|item| item.error)
+        .filter(|error| error.kind == "index_not_found_exception")
+        .filter_map(|error| error.index)
+        .collect::<Vec<_>>();
+    indices.sort();
+    indices.dedup();
+    indices
+}
+
+/// Whether an item failed because its shard was moving between nodes, which
+/// clears up once the relocation is done.
+fn is_relocation_error(error: &BulkItemError) -> bool {
+    match error.kind.as_str() {
+        "unavailable_shards_exception" | "no_shard_available_action_exception" => true,
+        "illegal_state_exception" | "shard_not_found_exception" => error
+            .reason
+            .as_ref()
+            .map_or(false, |reason| reason.contains("relocat")),
+        _ => false,
+    }
+}
+
+/// The number of items of a bulk response rejected because the bulk queue of
+/// their node was full, or 1 if the whole request was.
+pub(super) fn rejected_executions(response: &hyper::Response<Bytes>) -> usize {
+    const REJECTED: &str = "es_rejected_execution_exception";
+
+    if response.status() == hyper::StatusCode::TOO_MANY_REQUESTS {
+        let rejected = serde_json::from_slice::<serde_json::Value>(response.body())
+            .ok()
+            .map_or(false, |body| body["error"]["type"] == REJECTED);
+        return rejected as usize;
+    }
+    match serde_json::from_slice::<BulkItemsResponse>(response.body()) {
+        Ok(response) if response.errors => response
+            .items
+            .into_iter()
+            .flat_map(|item| item.into_iter().map(|(_, item)| item))
+            .filter_map(|item| item.error)
+            .filter(|error| error.kind == REJECTED)
+            .count(),
+        _ => 0,
+    }
+}
+
+/// The number of items of a bulk response that failed because their shard
+/// was relocating.
+pub(super) fn relocation_errors(response: &hyper::Response<Bytes>) -> usize {
+    match serde_json::from_slice::<BulkItemsResponse>(response.body()) {
+        Ok(response) if response.errors => response
+            .items
+            .into_iter()
+            .flat_map(|item| item.into_iter().map(|(_, item)| item))
+            .filter_map(|item| item.error)
+            .filter(is_relocation_error)
+            .count(),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct FailedItems {
+    pub(super) failed: Vec<FailedItem>,
+    pub(super) skipped_version_conflicts: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct FailedItem {
+    // The position of the document in the request.
+    pub(super) position: usize,
+    pub(super) id: Option<String>,
+    pub(super) index: Option<String>,
+    pub(super) kind: String,
+    pub(super) reason: Option<String>,
+}
+
+/// The documents of a bulk response that failed to be indexed.
+pub(super) fn failed_items(
+    response: &hyper::Response<Bytes>,
+    ignore_version_conflicts: bool,
+) -> FailedItems {
+    let response = match serde_json::from_slice::<BulkItemsResponse>(response.body()) {
+        Ok(response) if response.errors => response,
+        _ => return FailedItems::default(),
+    };
+
+    let mut failed = FailedItems::default();
+    let items = response
+        .items
+        .into_iter()
+        .flat_map(|item| item.into_iter().map(|(_, item)| item));
+    for (position, item) in items.enumerate() {
+        let error = match item.error {
+            Some(error) => error,
+            None => continue,
+        };
+        if ignore_version_conflicts && error.kind == "version_conflict_engine_exception" {
+            failed.skipped_version_conflicts += 1;
+        } else {
+            failed.failed.push(FailedItem {
+                position,
+                id: item.id,
+                index: item.index,
+                kind: error.kind,
+                reason: error.reason,
+            });
+        }
+    }
+    failed
+}
+
+impl RetryLogic for ElasticSearchRetryLogic {
+    type Error = hyper::Error;
+    type Response = hyper::Response<Bytes>;
+
+    fn is_retriable_error(&self, error: &Self::Error) -> bool {
+        // Nothing was sent over connections that couldn't be made.
+        HttpRetryLogic.is_retriable_error(error)
+            && (error.is_connect() || self.retry_ambiguous("connection closed"))
+    }
+
+    fn is_retriable_timeout(&self) -> bool {
+        self.retry_ambiguous("request timed out")
+    }
+
+    fn should_retry_response(&self, response: &Self::Response) -> bool {
+        if response.status().is_redirection() {
+            // Redirects that weren't followed fail the same way every time.
+            error!(
+                message = "Elasticsearch responded with a redirect; point the host at its target or set follow_redirects.",
+                status = %response.status(),
+                location = ?response.headers().get("Location"),
+                redirect_responses_counter = 1
+            );
+            return false;
+        }
+
+        if is_invalid_bulk_response(response) {
+            warn!(
+                message = "successful response is not a bulk response.",
+                body_bytes = response.body().len(),
+                invalid_bulk_response_counter = 1
+            );
+            return true;
+        }
+
+        let missing = missing_indices(response);
+        if !missing.is_empty() {
+            if self.auto_create_index {
+                // `CreateMissingIndices` has created them by now, but the
+                // documents of other indices were written.
+                return self.retry_ambiguous("missing indices");
+            }
+            error!(
+                message = "indices do not exist and the cluster does not create them automatically; set auto_create_index to create them.",
+                indices = ?missing,
+                index_not_found_counter = 1
+            );
+            return false;
+        }
+
+        let rejected = rejected_executions(response);
+        if rejected > 0 {
+            // The surest sign the cluster is overloaded, so it's counted apart
+            // from other throttling, and `retry_after` waits longest for it.
+            warn!(
+                message = "Elasticsearch's bulk queue is full; backing off.",
+                count = rejected,
+                bulk_queue_rejections_counter = rejected
+            );
+            // Whole requests rejected weren't written, but the other documents
+            // of one with rejected items were.
+            return response.status() == hyper::StatusCode::TOO_MANY_REQUESTS
+                || self.retry_ambiguous("rejected execution");
+        }
+
+        let relocating = relocation_errors(response);
+        if relocating > 0 {
+            if !self.retry_ambiguous("relocating shards") {
+                return false;
+            }
+            // Not a real failure, so it's counted apart from failed documents.
+            warn!(
+                message = "documents were rejected while their shards were relocating; retrying.",
+                count = relocating,
+                relocating_shard_retries_counter = relocating
+            );
+            return true;
+        }
+
+        // Throttled or unavailable clusters reject batches without writing
+        // them, but other server errors can come after a partial write.
+        let status = response.status();
+        HttpRetryLogic.should_retry_response(response)
+            && (status == hyper::StatusCode::TOO_MANY_REQUESTS
+                || status == hyper::StatusCode::SERVICE_UNAVAILABLE
+                || self.retry_ambiguous("server error"))
+    }
+
+    fn response_retry_limit(&self, response: &Self::Response) -> Option<usize> {
+        if is_invalid_bulk_response(response) {
+            Some(self.invalid_response_attempts)
+        } else {
+            None
+        }
+    }
+
+    fn retry_after(&self, response: &Self::Response) -> Option<Duration> {
+        let retry_after = HttpRetryLogic.retry_after(response);
+        if rejected_executions(response) > 0 {
+            let backoff = self.rejected_execution_backoff;
+            Some(retry_after.map_or(backoff, |retry_after| retry_after.max(backoff)))
+        } else {
+            retry_after
+        }
+    }
+}
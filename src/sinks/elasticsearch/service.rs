@@ -0,0 +1,1204 @@
+use super::{
+    encoder::{redact, redaction_pointers, Encoder},
+    retry::{failed_items, is_invalid_bulk_response, missing_indices},
+    BulkRequest,
+};
+use crate::{
+    event::{self, Event, LogEvent, ValueKind},
+    sinks::util::{http::HttpService, Batch, Partition, PartitionInnerBuffer},
+    sources::internal_events,
+};
+use bytes::Bytes;
+use futures::{sync::mpsc, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::{hash_map::DefaultHasher, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Delay;
+use tower::Service;
+
+/// Writes the body of every request that still fails once retries are done
+/// to a directory, overwriting the oldest dump after `max_files`.
+pub(super) struct BatchDumper {
+    pub(super) dir: PathBuf,
+    max_files: usize,
+    pub(super) next: AtomicUsize,
+}
+
+impl BatchDumper {
+    pub(super) fn new(dir: PathBuf, max_files: usize) -> Self {
+        Self {
+            dir,
+            max_files: std::cmp::max(max_files, 1),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn dump(&self, body: &[u8], gzip: bool) {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.max_files;
+        let extension = if gzip { "ndjson.gz" } else { "ndjson" };
+        let path = self
+            .dir
+            .join(format!("failed-batch-{}.{}", slot, extension));
+
+        let result = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, body));
+        match result {
+            Ok(()) => warn!(message = "dumped failed batch.", path = ?path),
+            Err(error) => error!(message = "unable to dump failed batch.", path = ?path, %error),
+        }
+    }
+}
+
+/// Turns the documents of failed batches back into events and forwards them
+/// to the configured dead letter sink.
+pub(super) struct DeadLetters {
+    pub(super) tx: mpsc::Sender<Event>,
+    pub(super) forward: Mutex<Option<Box<dyn Future<Item = (), Error = ()> + Send>>>,
+}
+
+impl DeadLetters {
+    pub(super) fn new(sink: crate::sinks::RouterSink) -> Self {
+        let (tx, rx) = mpsc::channel(100);
+        let forward = rx.forward(sink).map(|_| ());
+
+        Self {
+            tx,
+            forward: Mutex::new(Some(Box::new(forward))),
+        }
+    }
+
+    pub(super) fn send(&self, body: &[u8], gzip: bool) {
+        match decode_documents(body, gzip) {
+            Ok(events) => self.send_events(events),
+            Err(error) => {
+                error!(message = "unable to decode failed batch for dead letter sink.", %error)
+            }
+        }
+    }
+
+    /// Sends only the documents at the given positions of the request.
+    fn send_positions(&self, body: &[u8], gzip: bool, positions: &HashSet<usize>) {
+        match decode_documents(body, gzip) {
+            Ok(events) => self.send_events(
+                events
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(position, _)| positions.contains(position))
+                    .map(|(_, event)| event)
+                    .collect(),
+            ),
+            Err(error) => {
+                error!(message = "unable to decode failed batch for dead letter sink.", %error)
+            }
+        }
+    }
+
+    pub(super) fn send_events(&self, events: Vec<Event>) {
+        // The sink is only started once we're running on the runtime.
+        if let Some(forward) = self.forward.lock().unwrap().take() {
+            tokio::spawn(forward);
+        }
+
+        let mut tx = self.tx.clone();
+        for event in events {
+            if tx.try_send(event).is_err() {
+                warn!(
+                    message = "dead letter sink is full; dropping event.",
+                    dropped_dead_letters_counter = 1
+                );
+            } else {
+                debug!(
+                    message = "sent event to dead letter sink.",
+                    dead_letters_counter = 1
+                );
+            }
+        }
+    }
+}
+
+/// Decodes the document lines of a bulk request body back into events.
+pub(super) fn decode_documents(body: &[u8], gzip: bool) -> Result<Vec<Event>, String> {
+    let body = decompress(body, gzip)?;
+
+    // Lines alternate between an action and the document it applies to.
+    body.split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .skip(1)
+        .step_by(2)
+        .map(|line| {
+            serde_json::from_slice(line)
+                .map(document_to_event)
+                .map_err(|error| error.to_string())
+        })
+        .collect()
+}
+
+fn decompress(body: &[u8], gzip: bool) -> Result<Cow<[u8]>, String> {
+    if gzip {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_end(&mut decompressed)
+            .map_err(|error| error.to_string())?;
+        Ok(Cow::Owned(decompressed))
+    } else {
+        Ok(Cow::Borrowed(body))
+    }
+}
+
+fn document_to_event(document: serde_json::Value) -> Event {
+    let mut event = Event::new_empty_log();
+    insert_flattened(event.as_mut_log(), String::new(), document);
+    event
+}
+
+fn insert_flattened(log: &mut LogEvent, key: String, value: serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                let key = if key.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                insert_flattened(log, key, value);
+            }
+        }
+        Value::String(string) => log.insert_explicit(key.into(), string.into()),
+        Value::Bool(boolean) => log.insert_explicit(key.into(), boolean.into()),
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => log.insert_explicit(key.into(), integer.into()),
+            None => log.insert_explicit(key.into(), number.as_f64().unwrap_or(0.0).into()),
+        },
+        Value::Array(_) => log.insert_explicit(key.into(), value.to_string().into()),
+        Value::Null => {}
+    }
+}
+
+/// Dumps batches that failed for good, and forwards their documents to the
+/// dead letter sink. When a batch went through but some of its documents were
+/// rejected, only the rejected documents are forwarded.
+#[derive(Clone)]
+pub(super) struct DumpFailedBatches<S> {
+    pub(super) inner: S,
+    pub(super) dumper: Option<Arc<BatchDumper>>,
+    pub(super) dead_letters: Option<Arc<DeadLetters>>,
+    pub(super) ignore_version_conflicts: bool,
+    // Documents rejected by their mapping already reached the dead letter
+    // sink when they couldn't be coerced.
+    pub(super) coerces_mapping_errors: bool,
+}
+
+impl<S> DumpFailedBatches<S> {
+    pub(super) fn new(
+        inner: S,
+        dumper: Option<BatchDumper>,
+        dead_letters: Option<Arc<DeadLetters>>,
+    ) -> Self {
+        Self {
+            inner,
+            dumper: dumper.map(Arc::new),
+            dead_letters,
+            ignore_version_conflicts: false,
+            coerces_mapping_errors: false,
+        }
+    }
+
+    pub(super) fn ignore_version_conflicts(mut self, enabled: bool) -> Self {
+        self.ignore_version_conflicts = enabled;
+        self
+    }
+
+    pub(super) fn coerces_mapping_errors(mut self, enabled: bool) -> Self {
+        self.coerces_mapping_errors = enabled;
+        self
+    }
+}
+
+impl<S> Service<BulkRequest> for DumpFailedBatches<S>
+where
+    S: Service<BulkRequest, Response = hyper::Response<Bytes>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: BulkRequest) -> Self::Future {
+        if self.dumper.is_none() && self.dead_letters.is_none() {
+            return Box::new(self.inner.call(request));
+        }
+
+        let dumper = self.dumper.clone();
+        let dead_letters = self.dead_letters.clone();
+        let ignore_version_conflicts = self.ignore_version_conflicts;
+        let coerces_mapping_errors = self.coerces_mapping_errors;
+        let ((copy, gzip), _) = request.clone().into_parts();
+
+        Box::new(self.inner.call(request).then(move |result| {
+            let failed = match &result {
+                Ok(response) => {
+                    !response.status().is_success() || is_invalid_bulk_response(response)
+                }
+                Err(_) => true,
+            };
+            if failed {
+                if let Some(dumper) = dumper {
+                    dumper.dump(&copy, gzip);
+                }
+                if let Some(dead_letters) = dead_letters {
+                    dead_letters.send(&copy, gzip);
+                }
+            } else if let (Some(dead_letters), Ok(response)) = (dead_letters, &result) {
+                let positions = failed_items(response, ignore_version_conflicts)
+                    .failed
+                    .into_iter()
+                    .filter(|item| {
+                        !(coerces_mapping_errors && item.kind == "mapper_parsing_exception")
+                    })
+                    .map(|item| item.position)
+                    .collect::<HashSet<_>>();
+                if !positions.is_empty() {
+                    dead_letters.send_positions(&copy, gzip, &positions);
+                }
+            }
+            result
+        }))
+    }
+}
+
+/// Logs the documents of bulk responses that failed to be indexed once the
+/// request is done being retried. With external versioning, a version
+/// conflict means a newer version of the document is already indexed, so
+/// conflicts can be skipped as successes instead.
+///
+/// The first few failed documents are logged on their own, with their
+/// position in the request, their id and index, and optionally a sample of
+/// the document itself.
+///
+/// Requests that failed for good can also be emitted as internal events,
+/// with the raw response as their message, for `internal_events` sources to
+/// route anywhere in the topology.
+pub(super) struct ReportFailedItems<S> {
+    pub(super) inner: S,
+    pub(super) ignore_version_conflicts: bool,
+    pub(super) samples: Option<Arc<DocumentSamples>>,
+    pub(super) internal_events: bool,
+    request_ids: bool,
+}
+
+// Keeps a batch of documents all failing the same way from flooding the logs.
+const MAX_LOGGED_FAILED_ITEMS: usize = 5;
+
+impl<S> ReportFailedItems<S> {
+    pub(super) fn new(
+        inner: S,
+        ignore_version_conflicts: bool,
+        samples: Option<DocumentSamples>,
+    ) -> Self {
+        Self {
+            inner,
+            ignore_version_conflicts,
+            samples: samples.map(Arc::new),
+            internal_events: false,
+            request_ids: false,
+        }
+    }
+
+    pub(super) fn emit_internal_events(mut self, enabled: bool) -> Self {
+        self.internal_events = enabled;
+        self
+    }
+
+    pub(super) fn log_request_ids(mut self, enabled: bool) -> Self {
+        self.request_ids = enabled;
+        self
+    }
+}
+
+/// The id of a bulk request sent in `request_id_header`, for tracing it
+/// through proxies and the cluster's logs. It's derived from the contents of
+/// the request, so that every retry of it carries the same id.
+pub(super) fn request_id(request: &BulkRequest) -> String {
+    let mut hasher = DefaultHasher::new();
+    request.partition().hash(&mut hasher);
+    request.get_ref().hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn failure_event(response: &hyper::Response<Bytes>, failed_documents: usize) -> Event {
+    let mut event = Event::from(String::from_utf8_lossy(response.body()).into_owned());
+    let log = event.as_mut_log();
+    log.insert_explicit("component_type".into(), "elasticsearch".into());
+    log.insert_explicit(
+        "status".into(),
+        ValueKind::Integer(i64::from(response.status().as_u16())),
+    );
+    log.insert_explicit(
+        "failed_documents".into(),
+        ValueKind::Integer(failed_documents as i64),
+    );
+    event
+}
+
+/// Samples of failed documents, with sensitive fields redacted and cut down
+/// to at most `max_bytes`.
+pub(super) struct DocumentSamples {
+    pub(super) max_bytes: usize,
+    // JSON pointers to the redacted fields.
+    pub(super) redact: Vec<String>,
+}
+
+impl DocumentSamples {
+    pub(super) fn new(max_bytes: usize, redact: &[String]) -> Self {
+        Self {
+            max_bytes,
+            redact: redaction_pointers(redact),
+        }
+    }
+
+    pub(super) fn sample(&self, body: &[u8], gzip: bool, position: usize) -> Option<String> {
+        let body = decompress(body, gzip).ok()?;
+        let document = body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .nth(position * 2 + 1)?;
+        let mut sample = match serde_json::from_slice::<serde_json::Value>(document) {
+            Ok(mut document) => {
+                redact(&mut document, &self.redact);
+                document.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(document).into_owned(),
+        };
+        if sample.len() > self.max_bytes {
+            let mut end = self.max_bytes;
+            while !sample.is_char_boundary(end) {
+                end -= 1;
+            }
+            sample.truncate(end);
+            sample.push_str("...");
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Service<BulkRequest> for ReportFailedItems<S>
+where
+    S: Service<BulkRequest, Response = hyper::Response<Bytes>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: BulkRequest) -> Self::Future {
+        let ignore_version_conflicts = self.ignore_version_conflicts;
+        let internal_events = self.internal_events;
+        let samples = self.samples.clone();
+        let body = samples.as_ref().map(|_| request.clone().into_parts().0);
+        let request_id = if self.request_ids {
+            Some(request_id(&request))
+        } else {
+            None
+        };
+        Box::new(self.inner.call(request).inspect(move |response| {
+            let items = failed_items(response, ignore_version_conflicts);
+            if internal_events && (!response.status().is_success() || !items.failed.is_empty()) {
+                internal_events::emit(failure_event(response, items.failed.len()));
+            }
+            if items.skipped_version_conflicts > 0 {
+                debug!(
+                    message = "skipped documents with a newer version already indexed.",
+                    count = items.skipped_version_conflicts,
+                    skipped_version_conflicts_counter = items.skipped_version_conflicts
+                );
+            }
+            for item in items.failed.iter().take(MAX_LOGGED_FAILED_ITEMS) {
+                let document = match (&samples, &body) {
+                    (Some(samples), Some((body, gzip))) => {
+                        samples.sample(body, *gzip, item.position)
+                    }
+                    _ => None,
+                };
+                error!(
+                    message = "document failed to be indexed.",
+                    request_id = ?request_id,
+                    position = item.position,
+                    id = ?item.id,
+                    index = ?item.index,
+                    error_type = %item.kind,
+                    reason = ?item.reason,
+                    document = ?document
+                );
+            }
+            if let Some(first) = items.failed.first() {
+                error!(
+                    message = "documents failed to be indexed.",
+                    request_id = ?request_id,
+                    count = items.failed.len(),
+                    first_error = %first.kind,
+                    failed_documents_counter = items.failed.len()
+                );
+            }
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct BulkTook {
+    took: u64,
+}
+
+/// Records the time the cluster reports having spent processing each bulk
+/// request, so it can be told apart from the time spent on the network and
+/// waiting in queues.
+#[derive(Clone)]
+pub(super) struct RecordTook<S> {
+    pub(super) inner: S,
+}
+
+impl<S> RecordTook<S> {
+    pub(super) fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S> Service<BulkRequest> for RecordTook<S>
+where
+    S: Service<BulkRequest, Response = hyper::Response<Bytes>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: BulkRequest) -> Self::Future {
+        Box::new(self.inner.call(request).inspect(|response| {
+            if !response.status().is_success() {
+                return;
+            }
+            if let Ok(BulkTook { took }) = serde_json::from_slice(response.body()) {
+                trace!(elasticsearch_bulk_took_histogram = took);
+            }
+        }))
+    }
+}
+
+/// Creates the indices a bulk request failed to write to because they didn't
+/// exist, before the response is handed to the retry policy.
+#[derive(Clone)]
+pub(super) struct CreateMissingIndices<S> {
+    pub(super) inner: S,
+    creator: Option<HttpService<String>>,
+}
+
+impl<S> CreateMissingIndices<S> {
+    pub(super) fn new(inner: S, creator: Option<HttpService<String>>) -> Self {
+        Self { inner, creator }
+    }
+}
+
+impl<S> Service<BulkRequest> for CreateMissingIndices<S>
+where
+    S: Service<BulkRequest, Response = hyper::Response<Bytes>, Error = hyper::Error>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: BulkRequest) -> Self::Future {
+        let creator = match &self.creator {
+            Some(creator) => creator.clone(),
+            None => return Box::new(self.inner.call(request)),
+        };
+
+        Box::new(self.inner.call(request).and_then(move |response| {
+            let creating = missing_indices(&response)
+                .into_iter()
+                .map(|index| {
+                    creator.clone().call(index.clone()).then(move |result| {
+                        match result {
+                            Ok(ref created) if created.status().is_success() => {
+                                info!(message = "created missing index.", %index);
+                            }
+                            // Another request may have created it first.
+                            Ok(ref created)
+                                if String::from_utf8_lossy(created.body())
+                                    .contains("resource_already_exists_exception") => {}
+                            Ok(created) => error!(
+                                message = "failed to create missing index.",
+                                %index,
+                                status = %created.status()
+                            ),
+                            Err(error) => error!(
+                                message = "failed to create missing index.",
+                                %index,
+                                %error
+                            ),
+                        }
+                        Ok::<_, hyper::Error>(())
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            futures::future::join_all(creating).map(move |_| response)
+        }))
+    }
+}
+
+/// Resends, once, the documents of a bulk request that were rejected because
+/// a field didn't parse as its mapped type, with the field coerced to that
+/// type. The outcome of the resent documents replaces their failures in the
+/// response handed on. Documents that can't be coerced, or that fail again,
+/// go to the dead letter sink if there is one, and are otherwise dropped like
+/// any other failed document.
+#[derive(Clone)]
+pub(super) struct CoerceMappingErrors<S> {
+    pub(super) inner: S,
+    pub(super) enabled: bool,
+    pub(super) dead_letters: Option<Arc<DeadLetters>>,
+}
+
+impl<S> CoerceMappingErrors<S> {
+    pub(super) fn new(inner: S, enabled: bool, dead_letters: Option<Arc<DeadLetters>>) -> Self {
+        Self {
+            inner,
+            enabled,
+            dead_letters,
+        }
+    }
+}
+
+impl<S> Service<BulkRequest> for CoerceMappingErrors<S>
+where
+    S: Service<BulkRequest, Response = hyper::Response<Bytes>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: BulkRequest) -> Self::Future {
+        if !self.enabled {
+            return Box::new(self.inner.call(request));
+        }
+
+        let mut inner = self.inner.clone();
+        let dead_letters = self.dead_letters.clone();
+        let ((body, gzip), tenant) = request.clone().into_parts();
+
+        Box::new(self.inner.call(request).and_then(move |response| {
+            let coerced = match coerce_rejected_documents(&body, gzip, &response) {
+                Some(coerced) => coerced,
+                None => return Box::new(futures::future::ok(response)) as Self::Future,
+            };
+            send_dead_letters(&dead_letters, coerced.uncoerced);
+            if coerced.positions.is_empty() {
+                return Box::new(futures::future::ok(response));
+            }
+
+            debug!(
+                message = "resending documents with coerced fields.",
+                count = coerced.positions.len(),
+                coerced_documents_counter = coerced.positions.len()
+            );
+            let positions = coerced.positions;
+            let documents = coerced.documents;
+            let retry = PartitionInnerBuffer::new((coerced.body, false), tenant);
+            Box::new(inner.call(retry).then(
+                move |result| -> Result<hyper::Response<Bytes>, S::Error> {
+                    let merged = match result {
+                        Ok(retried) => merge_retried_items(&response, &positions, &retried)
+                            .map(|merged| (merged, retried)),
+                        Err(_) => None,
+                    };
+                    match merged {
+                        Some((merged, retried)) => {
+                            let failed = failed_items(&retried, false)
+                                .failed
+                                .into_iter()
+                                .filter_map(|item| documents.get(item.position).cloned())
+                                .collect();
+                            send_dead_letters(&dead_letters, failed);
+                            Ok(merged)
+                        }
+                        None => {
+                            error!(message = "failed to resend documents with coerced fields.");
+                            send_dead_letters(&dead_letters, documents);
+                            Ok(response)
+                        }
+                    }
+                },
+            ))
+        }))
+    }
+}
+
+/// The documents of a bulk request rejected by their mapping, coerced to be
+/// resent.
+pub(super) struct CoercedDocuments {
+    // The positions of the resent documents in the original request.
+    positions: Vec<usize>,
+    pub(super) documents: Vec<serde_json::Value>,
+    pub(super) body: Vec<u8>,
+    uncoerced: Vec<serde_json::Value>,
+}
+
+/// Coerces the fields the documents of a request were rejected for, if any
+/// were rejected by their mapping.
+fn coerce_rejected_documents(
+    body: &[u8],
+    gzip: bool,
+    response: &hyper::Response<Bytes>,
+) -> Option<CoercedDocuments> {
+    let rejected = failed_items(response, false)
+        .failed
+        .into_iter()
+        .filter(|item| item.kind == "mapper_parsing_exception")
+        .collect::<Vec<_>>();
+    if rejected.is_empty() {
+        return None;
+    }
+
+    let body = decompress(body, gzip).ok()?;
+    let lines = body
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut coerced = CoercedDocuments {
+        positions: Vec::new(),
+        documents: Vec::new(),
+        body: Vec::new(),
+        uncoerced: Vec::new(),
+    };
+    for item in rejected {
+        let (action, document) = match (
+            lines.get(item.position * 2),
+            lines.get(item.position * 2 + 1),
+        ) {
+            (Some(action), Some(document)) => (action, document),
+            _ => continue,
+        };
+        let mut document = match serde_json::from_slice::<serde_json::Value>(document) {
+            Ok(document) => document,
+            Err(_) => continue,
+        };
+
+        let error = item
+            .reason
+            .as_ref()
+            .and_then(|reason| parse_mapping_error(reason));
+        match error {
+            Some(error) if coerce_field(&mut document, &error.field, &error.kind) => {
+                coerced.body.extend_from_slice(action);
+                coerced.body.push(b'\n');
+                serde_json::to_writer(&mut coerced.body, &document).unwrap();
+                coerced.body.push(b'\n');
+                coerced.positions.push(item.position);
+                coerced.documents.push(document);
+            }
+            _ => {
+                warn!(
+                    message = "unable to coerce document rejected by its mapping.",
+                    reason = ?item.reason,
+                    uncoerced_documents_counter = 1
+                );
+                coerced.uncoerced.push(document);
+            }
+        }
+    }
+    Some(coerced)
+}
+
+/// A field that didn't parse as the type it's mapped to.
+#[derive(Debug, PartialEq)]
+pub(super) struct MappingError {
+    pub(super) field: String,
+    pub(super) kind: String,
+}
+
+lazy_static! {
+    static ref MAPPING_ERROR: Regex =
+        Regex::new(r"failed to parse (?:field )?\[([^\]]+)\] of type \[([^\]]+)\]").unwrap();
+}
+
+/// The field and type of a `mapper_parsing_exception` reason, like `failed
+/// to parse field [status] of type [long] in document with id '1'`.
+pub(super) fn parse_mapping_error(reason: &str) -> Option<MappingError> {
+    let captures = MAPPING_ERROR.captures(reason)?;
+    Some(MappingError {
+        field: captures[1].to_string(),
+        kind: captures[2].to_string(),
+    })
+}
+
+/// Coerces a field of a document, nested or dotted, to a mapping type.
+fn coerce_field(document: &mut serde_json::Value, field: &str, kind: &str) -> bool {
+    let nested = format!("/{}", field.replace('~', "~0").replace('.', "/"));
+    let pointer = if document.pointer(&nested).is_some() {
+        nested
+    } else {
+        format!("/{}", field.replace('~', "~0").replace('/', "~1"))
+    };
+
+    match document.pointer_mut(&pointer) {
+        Some(value) => match coerce_value(value, kind) {
+            Some(coerced) => {
+                *value = coerced;
+                true
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+fn coerce_value(value: &serde_json::Value, kind: &str) -> Option<serde_json::Value> {
+    use serde_json::Value;
+
+    match (kind, value) {
+        ("long", Value::String(string))
+        | ("integer", Value::String(string))
+        | ("short", Value::String(string))
+        | ("byte", Value::String(string)) => string.trim().parse::<i64>().ok().map(Value::from),
+        ("long", Value::Number(number))
+        | ("integer", Value::Number(number))
+        | ("short", Value::Number(number))
+        | ("byte", Value::Number(number)) => number
+            .as_f64()
+            .filter(|float| float.fract() == 0.0)
+            .map(|float| Value::from(float as i64)),
+        ("double", Value::String(string))
+        | ("float", Value::String(string))
+        | ("half_float", Value::String(string))
+        | ("scaled_float", Value::String(string)) => string
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|float| float.is_finite())
+            .map(Value::from),
+        ("keyword", Value::Number(number)) | ("text", Value::Number(number)) => {
+            Some(Value::String(number.to_string()))
+        }
+        ("keyword", Value::Bool(boolean)) | ("text", Value::Bool(boolean)) => {
+            Some(Value::String(boolean.to_string()))
+        }
+        ("boolean", Value::String(string)) => match string.trim() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The response to a bulk request with the items of the resent documents at
+/// `positions` replaced by those of the response to resending them.
+fn merge_retried_items(
+    response: &hyper::Response<Bytes>,
+    positions: &[usize],
+    retried: &hyper::Response<Bytes>,
+) -> Option<hyper::Response<Bytes>> {
+    if !retried.status().is_success() {
+        return None;
+    }
+    let retried = serde_json::from_slice::<serde_json::Value>(retried.body()).ok()?;
+    let retried = retried.get("items")?.as_array()?;
+    if retried.len() != positions.len() {
+        return None;
+    }
+
+    let mut merged = serde_json::from_slice::<serde_json::Value>(response.body()).ok()?;
+    let errors = {
+        let items = merged.get_mut("items")?.as_array_mut()?;
+        for (position, item) in positions.iter().zip(retried) {
+            *items.get_mut(*position)? = item.clone();
+        }
+        items.iter().any(|item| {
+            item.as_object().map_or(false, |item| {
+                item.values().any(|item| item.get("error").is_some())
+            })
+        })
+    };
+    merged["errors"] = json!(errors);
+
+    let mut builder = hyper::Response::builder();
+    builder.status(response.status());
+    for (name, value) in response.headers() {
+        if name != http::header::CONTENT_LENGTH {
+            builder.header(name, value);
+        }
+    }
+    builder.body(Bytes::from(merged.to_string())).ok()
+}
+
+fn send_dead_letters(dead_letters: &Option<Arc<DeadLetters>>, documents: Vec<serde_json::Value>) {
+    if let Some(dead_letters) = dead_letters {
+        if !documents.is_empty() {
+            dead_letters.send_events(documents.into_iter().map(document_to_event).collect());
+        }
+    }
+}
+
+/// Paces the first write to indices that haven't been seen before, so that a
+/// burst of new indices (e.g. at a daily rollover across many tenants) doesn't
+/// have the cluster create them all at once. Writes to indices already seen
+/// are never held back.
+pub(super) struct IndexCreationThrottle<S> {
+    pub(super) inner: S,
+    pub(super) encoder: Encoder,
+    seen: HashSet<String>,
+    pub(super) limit: usize,
+    period: Duration,
+    window: Option<Delay>,
+    created: usize,
+}
+
+impl<S> IndexCreationThrottle<S> {
+    pub(super) fn new(inner: S, encoder: Encoder, limit: usize, period: Duration) -> Self {
+        Self {
+            inner,
+            encoder,
+            seen: HashSet::new(),
+            limit: std::cmp::max(limit, 1),
+            period,
+            window: None,
+            created: 0,
+        }
+    }
+
+    fn new_index(&self, event: &Event) -> Option<String> {
+        match event {
+            Event::Log(_) => self
+                .encoder
+                .log_index(event)
+                .render_string(event)
+                .ok()
+                .filter(|index| !self.seen.contains(index)),
+            Event::Metric(_) => None,
+        }
+    }
+}
+
+impl<S: Sink<SinkItem = Event>> Sink for IndexCreationThrottle<S> {
+    type SinkItem = Event;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if let Some(index) = self.new_index(&event) {
+            if let Some(window) = &mut self.window {
+                match window.poll() {
+                    Ok(Async::NotReady) if self.created >= self.limit => {
+                        debug!(
+                            message = "pacing write to new index.",
+                            %index,
+                            throttled_index_creations_counter = 1
+                        );
+                        return Ok(AsyncSink::NotReady(event));
+                    }
+                    Ok(Async::NotReady) => {}
+                    Ok(Async::Ready(())) => self.window = None,
+                    Err(error) => {
+                        error!(message = "index creation timer failed.", %error);
+                        self.window = None;
+                    }
+                }
+            }
+
+            if self.window.is_none() {
+                self.window = Some(Delay::new(Instant::now() + self.period));
+                self.created = 0;
+            }
+            self.created += 1;
+            self.seen.insert(index);
+        }
+
+        self.inner.start_send(event)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.close()
+    }
+}
+
+/// Drops empty batches instead of sending them on, as flushing at shutdown
+/// may hand over a batch with nothing in it, and an empty `_bulk` request is
+/// rejected.
+pub(super) struct SkipEmptyBatches<S> {
+    pub(super) inner: S,
+}
+
+impl<S> SkipEmptyBatches<S> {
+    pub(super) fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S> Sink for SkipEmptyBatches<S>
+where
+    S: Sink,
+    S::SinkItem: Batch,
+{
+    type SinkItem = S::SinkItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, batch: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if batch.is_empty() {
+            trace!(message = "skipping empty batch.");
+            return Ok(AsyncSink::Ready);
+        }
+        self.inner.start_send(batch)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.close()
+    }
+}
+
+pub(super) const DEFAULT_COALESCE_COUNT_FIELD: &str = "_coalesced_count";
+
+pub(super) struct Coalescing {
+    // Compares every field but the timestamp when unset.
+    pub(super) keys: Option<Vec<Atom>>,
+    pub(super) count_field: Atom,
+    pub(super) uncoalesced_warned: AtomicBool,
+}
+
+impl Coalescing {
+    /// Whether the event can be part of a run. Events that already have the
+    /// count field are passed on alone, as the count would overwrite it.
+    fn coalesces(&self, log: &LogEvent) -> bool {
+        if log.get(&self.count_field).is_none() {
+            return true;
+        }
+        if !self.uncoalesced_warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                message = "not coalescing events that already have the count field; this is only logged once per sink.",
+                count_field = %self.count_field,
+            );
+        }
+        false
+    }
+
+    fn identical(&self, a: &LogEvent, b: &LogEvent) -> bool {
+        match &self.keys {
+            Some(keys) => keys.iter().all(|key| a.get(key) == b.get(key)),
+            None => {
+                let fields =
+                    |log: &LogEvent| log.keys().filter(|key| **key != *event::TIMESTAMP).count();
+                fields(a) == fields(b)
+                    && a.keys()
+                        .filter(|key| **key != *event::TIMESTAMP)
+                        .all(|key| a.get(key) == b.get(key))
+            }
+        }
+    }
+}
+
+/// Coalesces runs of consecutive identical log events into the first event
+/// of the run, with the length of the run in the count field. The run is
+/// passed on with its length so that all of its events are acked together.
+/// A run ends at the first different event or when the sink is flushed, so
+/// runs don't span batches sent while the source is idle. Without
+/// coalescing, every event is passed on as a run of one.
+pub(super) struct CoalesceIdentical<S> {
+    pub(super) inner: S,
+    pub(super) coalescing: Option<Coalescing>,
+    pub(super) run: Option<(Event, usize)>,
+}
+
+impl<S> CoalesceIdentical<S> {
+    pub(super) fn new(inner: S, coalescing: Option<Coalescing>) -> Self {
+        Self {
+            inner,
+            coalescing,
+            run: None,
+        }
+    }
+}
+
+impl<S: Sink<SinkItem = (Event, usize)>> CoalesceIdentical<S> {
+    fn continues_run(&self, event: &Event) -> bool {
+        match (&self.coalescing, &self.run, event) {
+            (Some(coalescing), Some((Event::Log(run), _)), Event::Log(log)) => {
+                coalescing.coalesces(log) && coalescing.identical(run, log)
+            }
+            _ => false,
+        }
+    }
+
+    // Runs only start from events without the count field, so it's theirs to
+    // set and to remove again.
+    fn flush_run(&mut self) -> Poll<(), S::SinkError> {
+        if let Some((mut event, count)) = self.run.take() {
+            if let Some(coalescing) = &self.coalescing {
+                if let Event::Log(log) = &mut event {
+                    log.insert_explicit(
+                        coalescing.count_field.clone(),
+                        ValueKind::Integer(count as i64),
+                    );
+                }
+            }
+            if let AsyncSink::NotReady((mut event, count)) =
+                self.inner.start_send((event, count))?
+            {
+                if let Some(coalescing) = &self.coalescing {
+                    event.as_mut_log().remove(&coalescing.count_field);
+                }
+                self.run = Some((event, count));
+                return Ok(Async::NotReady);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<S: Sink<SinkItem = (Event, usize)>> Sink for CoalesceIdentical<S> {
+    type SinkItem = Event;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.continues_run(&event) {
+            if let Some((_, count)) = &mut self.run {
+                *count += 1;
+            }
+            return Ok(AsyncSink::Ready);
+        }
+
+        if self.flush_run()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(event));
+        }
+        let starts_run = match (&self.coalescing, &event) {
+            (Some(coalescing), Event::Log(log)) => coalescing.coalesces(log),
+            _ => false,
+        };
+        match event {
+            event if starts_run => {
+                self.run = Some((event, 1));
+                Ok(AsyncSink::Ready)
+            }
+            event => match self.inner.start_send((event, 1))? {
+                AsyncSink::Ready => Ok(AsyncSink::Ready),
+                AsyncSink::NotReady((event, _)) => Ok(AsyncSink::NotReady(event)),
+            },
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.flush_run());
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.flush_run());
+        self.inner.close()
+    }
+}
+
+/// Forwards every event to `primary` and a best-effort copy to `shadow`.
+/// Errors or backpressure from the shadow are logged and otherwise ignored.
+pub(super) struct ShadowSink<S, T> {
+    pub(super) primary: S,
+    pub(super) shadow: T,
+}
+
+impl<S, T> ShadowSink<S, T> {
+    pub(super) fn new(primary: S, shadow: T) -> Self {
+        Self { primary, shadow }
+    }
+}
+
+impl<S, T> Sink for ShadowSink<S, T>
+where
+    S: Sink<SinkItem = Event, SinkError = ()>,
+    T: Sink<SinkItem = Event, SinkError = ()>,
+{
+    type SinkItem = Event;
+    type SinkError = ();
+
+    fn start_send(&mut self, event: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let copy = event.clone();
+
+        if let AsyncSink::NotReady(event) = self.primary.start_send(event)? {
+            return Ok(AsyncSink::NotReady(event));
+        }
+
+        match self.shadow.start_send(copy) {
+            Ok(AsyncSink::Ready) => {}
+            Ok(AsyncSink::NotReady(_)) => {
+                debug!(
+                    message = "shadow index not ready; dropping copy of event.",
+                    shadow_dropped_events_counter = 1
+                );
+            }
+            Err(()) => {
+                warn!(
+                    message = "shadow index failed; dropping copy of event.",
+                    shadow_dropped_events_counter = 1
+                );
+            }
+        }
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        if let Err(()) = self.shadow.poll_complete() {
+            warn!(message = "shadow index failed to flush.");
+        }
+
+        self.primary.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        if let Err(()) = self.shadow.close() {
+            warn!(message = "shadow index failed to close.");
+        }
+
+        self.primary.close()
+    }
+}
@@ -23,3 +23,6 @@ use crate::Event;
 pub type RouterSink = Box<dyn Sink<SinkItem = Event, SinkError = ()> + 'static + Send>;
 
 pub type Healthcheck = Box<dyn Future<Item = (), Error = String> + Send>;
+
+/// Makes a sink's healthcheck anew, to run it again after it failed.
+pub type HealthcheckFactory = Box<dyn Fn() -> Healthcheck + Send>;
@@ -127,11 +127,11 @@ impl SinkConfig for TcpSinkConfig {
     }
 }
 
-fn load_certificate<T: AsRef<Path> + Debug>(filename: T) -> Result<Certificate, String> {
+pub(crate) fn load_certificate<T: AsRef<Path> + Debug>(filename: T) -> Result<Certificate, String> {
     open_read_parse(filename, "certificate authority", Certificate::from_pem)
 }
 
-fn load_key<T: AsRef<Path> + Debug>(
+pub(crate) fn load_key<T: AsRef<Path> + Debug>(
     filename: T,
     pass_phrase: &Option<String>,
 ) -> Result<PKey<Private>, String> {
@@ -143,7 +143,7 @@ fn load_key<T: AsRef<Path> + Debug>(
     }
 }
 
-fn load_x509<T: AsRef<Path> + Debug>(filename: T) -> Result<X509, String> {
+pub(crate) fn load_x509<T: AsRef<Path> + Debug>(filename: T) -> Result<X509, String> {
     open_read_parse(filename, "certificate", X509::from_pem)
 }
 
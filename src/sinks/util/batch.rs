@@ -1,5 +1,5 @@
 use futures::{try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::timer::Delay;
 
 pub trait Batch {
@@ -11,6 +11,11 @@ pub trait Batch {
     fn fresh(&self) -> Self;
     fn finish(self) -> Self::Output;
     fn num_items(&self) -> usize;
+
+    /// Whether the batch wants to be sent regardless of its size or age.
+    fn should_flush(&self) -> bool {
+        false
+    }
 }
 
 impl<T> Batch for Vec<T> {
@@ -49,7 +54,11 @@ pub struct BatchSink<B, S> {
     min_size: usize,
     closing: bool,
     max_linger: Option<Duration>,
+    aligned_linger: bool,
     linger_deadline: Option<Delay>,
+    min_items: usize,
+    max_age: Option<Duration>,
+    age_deadline: Option<Delay>,
 }
 
 impl<B, S> BatchSink<B, S>
@@ -80,16 +89,50 @@ where
             min_size,
             closing: false,
             max_linger,
+            aligned_linger: false,
             linger_deadline: None,
+            min_items: 0,
+            max_age: None,
+            age_deadline: None,
         }
     }
 
+    /// Flush lingering batches on wall clock multiples of the linger time,
+    /// e.g. every minute on the minute, instead of relative to their first item
+    pub fn aligned_linger(mut self, aligned: bool) -> Self {
+        self.aligned_linger = aligned;
+        self
+    }
+
+    /// Keep lingering batches of fewer than `min_items` items until they
+    /// reach it or until `max_age` after their first item, so that a trickle
+    /// of items isn't sent one tiny batch at a time
+    pub fn min_items(mut self, min_items: usize, max_age: Duration) -> Self {
+        self.min_items = min_items;
+        self.max_age = Some(max_age);
+        self
+    }
+
     pub fn into_inner(self) -> S {
         self.inner
     }
 
     fn should_send(&mut self) -> bool {
-        self.closing || self.batch.len() >= self.min_size || self.linger_elapsed()
+        if self.closing || self.batch.len() >= self.min_size || self.batch.should_flush() {
+            return true;
+        }
+        // Poll both deadlines so that whichever comes next wakes us up.
+        let lingered = self.linger_elapsed();
+        let aged = self.age_elapsed();
+        (lingered && self.batch.num_items() >= self.min_items) || aged
+    }
+
+    fn age_elapsed(&mut self) -> bool {
+        if let Some(delay) = &mut self.age_deadline {
+            delay.poll().expect("timer error").is_ready()
+        } else {
+            false
+        }
     }
 
     fn linger_elapsed(&mut self) -> bool {
@@ -108,6 +151,7 @@ where
             Ok(Async::NotReady)
         } else {
             self.linger_deadline = None;
+            self.age_deadline = None;
             Ok(Async::Ready(()))
         }
     }
@@ -127,10 +171,10 @@ where
     // and need to push it down to the inner sink. The other case, when our batch is not full but
     // we want to push it to the inner sink anyway, can be detected and handled by poll_complete.
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        if self.batch.len() >= self.max_size {
+        if self.batch.len() >= self.max_size || self.batch.should_flush() {
             self.poll_complete()?;
 
-            if self.batch.len() > self.max_size {
+            if self.batch.len() > self.max_size || self.batch.should_flush() {
                 return Ok(AsyncSink::NotReady(item));
             }
         }
@@ -139,7 +183,11 @@ where
             if let Some(duration) = &self.max_linger {
                 // We just inserted the first item of a new batch, so set our delay to the longest time
                 // we want to allow that item to linger in the batch before being flushed.
-                self.linger_deadline = Some(Delay::new(Instant::now() + duration.clone()));
+                let deadline = linger_deadline(*duration, self.aligned_linger);
+                self.linger_deadline = Some(Delay::new(deadline));
+            }
+            if let (Some(max_age), true) = (self.max_age, self.min_items > 0) {
+                self.age_deadline = Some(Delay::new(Instant::now() + max_age));
             }
         }
 
@@ -194,11 +242,34 @@ where
     }
 }
 
+/// When a batch started now should be flushed. Aligned deadlines fall on the
+/// next wall clock multiple of `linger`.
+pub fn linger_deadline(linger: Duration, aligned: bool) -> Instant {
+    if !aligned {
+        return Instant::now() + linger;
+    }
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Instant::now() + until_aligned(linger, since_epoch)
+}
+
+fn until_aligned(period: Duration, since_epoch: Duration) -> Duration {
+    let period = period.as_nanos();
+    if period == 0 {
+        return Duration::from_secs(0);
+    }
+    let remainder = since_epoch.as_nanos() % period;
+    Duration::from_nanos((period - remainder) as u64)
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Batch, BatchSink};
+    use super::{until_aligned, Batch, BatchSink};
     use crate::sinks::util::Buffer;
-    use futures::{Future, Sink};
+    use futures::{Future, Sink, Stream};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tokio01_test::clock;
 
     #[test]
     fn batch_sink_buffers_messages_until_limit() {
@@ -263,4 +334,75 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn batch_sink_holds_small_batches_until_min_items_or_max_age() {
+        let new_sink = || {
+            BatchSink::new_min(Vec::new(), Vec::new(), 10, Some(Duration::from_secs(1)))
+                .min_items(3, Duration::from_secs(5))
+        };
+
+        // A single item outlives the linger and is only sent at the max age.
+        let mut buffered = new_sink();
+        clock::mock(|handle| {
+            buffered.start_send(1).unwrap();
+            buffered.poll_complete().unwrap();
+
+            handle.advance(Duration::from_secs(2));
+            buffered.poll_complete().unwrap();
+            assert!(buffered.inner.is_empty());
+
+            handle.advance(Duration::from_secs(4));
+            buffered.poll_complete().unwrap();
+        });
+        assert_eq!(vec![vec![1]], buffered.into_inner());
+
+        // Enough items are sent as soon as they've lingered.
+        let mut buffered = new_sink();
+        clock::mock(|handle| {
+            for i in 1..=3 {
+                buffered.start_send(i).unwrap();
+            }
+            buffered.poll_complete().unwrap();
+            assert!(buffered.inner.is_empty());
+
+            handle.advance(Duration::from_secs(2));
+            buffered.poll_complete().unwrap();
+        });
+        assert_eq!(vec![vec![1, 2, 3]], buffered.into_inner());
+    }
+
+    #[test]
+    fn batch_sink_aligns_lingering_flushes_to_wall_clock() {
+        assert_eq!(
+            Duration::from_secs(45),
+            until_aligned(Duration::from_secs(60), Duration::from_secs(600_015))
+        );
+        assert_eq!(
+            Duration::from_secs(60),
+            until_aligned(Duration::from_secs(60), Duration::from_secs(600_000))
+        );
+        assert_eq!(
+            Duration::from_millis(1),
+            until_aligned(Duration::from_millis(250), Duration::from_millis(1_249))
+        );
+
+        let linger = Duration::from_millis(200);
+        let (tx, rx) = futures::sync::mpsc::unbounded();
+        let buffered = BatchSink::new_min(tx, Vec::new(), 10, Some(linger)).aligned_linger(true);
+
+        // Sending only completes once the linger deadline flushes the batch.
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(buffered.send(0)).unwrap();
+        let flushed_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let (batch, _) = rx.into_future().wait().ok().unwrap();
+
+        assert_eq!(Some(vec![0]), batch);
+        let past_boundary = flushed_at.as_millis() % linger.as_millis();
+        assert!(
+            past_boundary < 100,
+            "flushed {}ms past a boundary",
+            past_boundary
+        );
+    }
 }
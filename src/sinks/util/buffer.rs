@@ -1,6 +1,7 @@
 use super::{batch::Batch, partition::Partition};
 use flate2::write::GzEncoder;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::io::Write;
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
@@ -14,6 +15,8 @@ pub enum Compression {
 pub struct Buffer {
     inner: InnerBuffer,
     num_items: usize,
+    gzip_threshold: Option<usize>,
+    gzip_level: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -32,9 +35,38 @@ impl Buffer {
         Self {
             inner,
             num_items: 0,
+            gzip_threshold: None,
+            gzip_level: None,
         }
     }
 
+    /// A buffer that stays uncompressed until it holds `threshold` bytes, so
+    /// small batches aren't worth the CPU of compressing them.
+    pub fn with_gzip_threshold(threshold: usize) -> Self {
+        Self {
+            inner: InnerBuffer::Plain(Vec::new()),
+            num_items: 0,
+            gzip_threshold: Some(threshold),
+            gzip_level: None,
+        }
+    }
+
+    /// Compresses at `level` (0-9) instead of the default, on this buffer and
+    /// every fresh one made from it. Must be called before anything is pushed.
+    pub fn gzip_level(mut self, level: u32) -> Self {
+        self.gzip_level = Some(level);
+        if let InnerBuffer::Gzip(_) = self.inner {
+            self.inner = InnerBuffer::Gzip(GzEncoder::new(Vec::new(), self.level()));
+        }
+        self
+    }
+
+    fn level(&self) -> flate2::Compression {
+        self.gzip_level
+            .map(flate2::Compression::new)
+            .unwrap_or_default()
+    }
+
     pub fn push(&mut self, input: &[u8]) {
         self.num_items += 1;
         match &mut self.inner {
@@ -45,6 +77,14 @@ impl Buffer {
                 inner.write_all(input).unwrap();
             }
         }
+
+        if let (InnerBuffer::Plain(plain), Some(threshold)) = (&self.inner, self.gzip_threshold) {
+            if plain.len() >= threshold {
+                let mut gzip = GzEncoder::new(Vec::new(), self.level());
+                gzip.write_all(plain).unwrap();
+                self.inner = InnerBuffer::Gzip(gzip);
+            }
+        }
     }
 
     // This is not guaranteed to be completely accurate as the gzip library does
@@ -81,15 +121,22 @@ impl Batch for Buffer {
     }
 
     fn fresh(&self) -> Self {
+        if let Some(threshold) = self.gzip_threshold {
+            return Self {
+                gzip_level: self.gzip_level,
+                ..Self::with_gzip_threshold(threshold)
+            };
+        }
+
         let inner = match &self.inner {
             InnerBuffer::Plain(_) => InnerBuffer::Plain(Vec::new()),
-            InnerBuffer::Gzip(_) => {
-                InnerBuffer::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
-            }
+            InnerBuffer::Gzip(_) => InnerBuffer::Gzip(GzEncoder::new(Vec::new(), self.level())),
         };
         Self {
             inner,
             num_items: 0,
+            gzip_threshold: None,
+            gzip_level: self.gzip_level,
         }
     }
 
@@ -107,6 +154,208 @@ impl Batch for Buffer {
     }
 }
 
+/// A `Buffer` that finishes into its body along with whether it was gzipped,
+/// which can differ between the batches of a `Buffer::with_gzip_threshold`.
+#[derive(Debug)]
+pub struct GzipFlaggedBuffer(Buffer);
+
+impl GzipFlaggedBuffer {
+    pub fn new(inner: Buffer) -> Self {
+        GzipFlaggedBuffer(inner)
+    }
+}
+
+impl Batch for GzipFlaggedBuffer {
+    type Input = Vec<u8>;
+    type Output = (Vec<u8>, bool);
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn push(&mut self, item: Self::Input) {
+        self.0.push(&item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        GzipFlaggedBuffer(self.0.fresh())
+    }
+
+    fn finish(self) -> Self::Output {
+        let gzip = match self.0.inner {
+            InnerBuffer::Plain(_) => false,
+            InnerBuffer::Gzip(_) => true,
+        };
+        (self.0.finish(), gzip)
+    }
+
+    fn num_items(&self) -> usize {
+        self.0.num_items()
+    }
+}
+
+/// A batch that asks to be flushed as soon as an item marked as a boundary
+/// has been pushed into it.
+#[derive(Debug)]
+pub struct BoundaryBuffer<T> {
+    inner: T,
+    boundary: bool,
+}
+
+impl<T> BoundaryBuffer<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            boundary: false,
+        }
+    }
+}
+
+impl<T: Batch> Batch for BoundaryBuffer<T> {
+    type Input = (T::Input, bool);
+    type Output = T::Output;
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn push(&mut self, (item, boundary): Self::Input) {
+        self.boundary |= boundary;
+        self.inner.push(item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        Self::new(self.inner.fresh())
+    }
+
+    fn finish(self) -> Self::Output {
+        self.inner.finish()
+    }
+
+    fn num_items(&self) -> usize {
+        self.inner.num_items()
+    }
+
+    fn should_flush(&self) -> bool {
+        self.boundary || self.inner.should_flush()
+    }
+}
+
+/// A batch that asks to be flushed once its items span `max_keys` distinct
+/// keys, e.g. sessions, so that related items are sent together. Items
+/// without a key aren't counted.
+#[derive(Debug)]
+pub struct DistinctKeysBuffer<T> {
+    inner: T,
+    keys: HashSet<String>,
+    max_keys: Option<usize>,
+}
+
+impl<T> DistinctKeysBuffer<T> {
+    pub fn new(inner: T, max_keys: Option<usize>) -> Self {
+        Self {
+            inner,
+            keys: HashSet::new(),
+            max_keys,
+        }
+    }
+}
+
+impl<T: Batch> Batch for DistinctKeysBuffer<T> {
+    type Input = (T::Input, Option<String>);
+    type Output = T::Output;
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn push(&mut self, (item, key): Self::Input) {
+        if let (Some(key), Some(_)) = (key, self.max_keys) {
+            self.keys.insert(key);
+        }
+        self.inner.push(item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        Self::new(self.inner.fresh(), self.max_keys)
+    }
+
+    fn finish(self) -> Self::Output {
+        self.inner.finish()
+    }
+
+    fn num_items(&self) -> usize {
+        self.inner.num_items()
+    }
+
+    fn should_flush(&self) -> bool {
+        self.max_keys.map_or(false, |max| self.keys.len() >= max) || self.inner.should_flush()
+    }
+}
+
+/// A batch whose items each stand for a given number of events, e.g. events
+/// coalesced into a single document, so that all of them are acked.
+#[derive(Debug)]
+pub struct CountedBuffer<T> {
+    inner: T,
+    num_items: usize,
+}
+
+impl<T> CountedBuffer<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            num_items: 0,
+        }
+    }
+}
+
+impl<T: Batch> Batch for CountedBuffer<T> {
+    type Input = (T::Input, usize);
+    type Output = T::Output;
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn push(&mut self, (item, count): Self::Input) {
+        self.num_items += count;
+        self.inner.push(item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        Self::new(self.inner.fresh())
+    }
+
+    fn finish(self) -> Self::Output {
+        self.inner.finish()
+    }
+
+    fn num_items(&self) -> usize {
+        self.num_items
+    }
+
+    fn should_flush(&self) -> bool {
+        self.inner.should_flush()
+    }
+}
+
 #[derive(Debug)]
 pub struct PartitionBuffer<T, K> {
     inner: T,
@@ -147,6 +396,10 @@ where
         self.inner.is_empty()
     }
 
+    fn should_flush(&self) -> bool {
+        self.inner.should_flush()
+    }
+
     fn fresh(&self) -> Self {
         Self {
             inner: self.inner.fresh(),
@@ -170,6 +423,10 @@ impl<T, K> PartitionInnerBuffer<T, K> {
         Self { inner, key }
     }
 
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
     pub fn into_parts(self) -> (T, K) {
         (self.inner, self.key)
     }
@@ -186,11 +443,87 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::Buffer;
+    use super::{BoundaryBuffer, Buffer, CountedBuffer, DistinctKeysBuffer, GzipFlaggedBuffer};
     use crate::sinks::util::batch::{Batch, BatchSink};
     use futures::{Future, Sink};
     use std::io::Read;
 
+    #[test]
+    fn boundary_buffer_flushes_after_marked_items() {
+        let buffered = BatchSink::new(vec![], BoundaryBuffer::new(Vec::new()), 10);
+
+        let input = vec![
+            (0, false),
+            (1, true),
+            (2, false),
+            (3, false),
+            (4, true),
+            (5, true),
+            (6, false),
+        ];
+        let (buffered, _) = buffered
+            .send_all(futures::stream::iter_ok(input))
+            .wait()
+            .unwrap();
+
+        let output = buffered
+            .into_inner()
+            .into_iter()
+            .map(|buf| buf.finish())
+            .collect::<Vec<Vec<i32>>>();
+
+        assert_eq!(output, vec![vec![0, 1], vec![2, 3, 4], vec![5], vec![6]]);
+    }
+
+    #[test]
+    fn distinct_keys_buffer_flushes_once_enough_keys_are_seen() {
+        let buffered = BatchSink::new(vec![], DistinctKeysBuffer::new(Vec::new(), Some(2)), 100);
+
+        let input = vec![
+            (0, Some("a")),
+            (1, Some("a")),
+            (2, None),
+            (3, Some("b")),
+            (4, Some("c")),
+            (5, Some("c")),
+            (6, Some("c")),
+            (7, Some("d")),
+            (8, Some("e")),
+        ]
+        .into_iter()
+        .map(|(item, key)| (item, key.map(String::from)));
+        let (buffered, _) = buffered
+            .send_all(futures::stream::iter_ok(input))
+            .wait()
+            .unwrap();
+
+        let output = buffered
+            .into_inner()
+            .into_iter()
+            .map(|buf| buf.finish())
+            .collect::<Vec<Vec<i32>>>();
+
+        assert_eq!(output, vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8]]);
+
+        // Without a limit keys never flush the batch.
+        let mut buffer = DistinctKeysBuffer::new(Vec::new(), None);
+        buffer.push((0, Some("a".to_string())));
+        buffer.push((1, Some("b".to_string())));
+        assert!(!buffer.should_flush());
+    }
+
+    #[test]
+    fn counted_buffer_counts_the_events_of_each_item() {
+        let mut buffer = CountedBuffer::new(Vec::new());
+        buffer.push(("a", 1));
+        buffer.push(("b", 3));
+
+        assert_eq!(2, buffer.len());
+        assert_eq!(4, buffer.num_items());
+        assert_eq!(0, buffer.fresh().num_items());
+        assert_eq!(vec!["a", "b"], buffer.finish());
+    }
+
     #[test]
     fn gzip() {
         use flate2::read::GzDecoder;
@@ -230,4 +563,66 @@ mod test {
         .take(100_000)
         .flatten()));
     }
+    #[test]
+    fn gzip_level_applies_to_fresh_buffers() {
+        use flate2::read::GzDecoder;
+
+        let input = std::iter::repeat(b"It's going down, I'm yelling timber".to_vec())
+            .take(100)
+            .flatten()
+            .collect::<Vec<u8>>();
+        let compress = |buffer: Buffer| {
+            let mut fresh = buffer.fresh();
+            fresh.push(&input);
+            let output = fresh.finish();
+
+            let mut decompressed = vec![];
+            GzDecoder::new(output.as_slice())
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(input, decompressed);
+            output.len()
+        };
+
+        let stored = compress(Buffer::new(true).gzip_level(0));
+        let best = compress(Buffer::new(true).gzip_level(9));
+        let threshold = compress(Buffer::with_gzip_threshold(10).gzip_level(0));
+
+        assert!(stored > input.len());
+        assert!(best < input.len() / 10);
+        assert!(threshold > input.len());
+    }
+
+    #[test]
+    fn gzip_threshold_keeps_default_level() {
+        let input = std::iter::repeat(b"It's going down, I'm yelling timber".to_vec())
+            .take(100)
+            .flatten()
+            .collect::<Vec<u8>>();
+        let compress = |buffer: Buffer| {
+            let mut fresh = buffer.fresh();
+            fresh.push(&input);
+            fresh.finish()
+        };
+
+        assert_eq!(
+            compress(Buffer::new(true)),
+            compress(Buffer::with_gzip_threshold(10))
+        );
+    }
+
+    #[test]
+    fn gzip_flagged_buffer_reports_compression_per_batch() {
+        let buffer = GzipFlaggedBuffer::new(Buffer::with_gzip_threshold(10));
+
+        let mut small = buffer.fresh();
+        small.push(b"tiny".to_vec());
+        assert_eq!((b"tiny".to_vec(), false), small.finish());
+
+        let mut large = buffer.fresh();
+        large.push(b"large enough to compress".to_vec());
+        let (body, gzip) = large.finish();
+        assert!(gzip);
+        assert_ne!(b"large enough to compress".to_vec(), body);
+    }
 }
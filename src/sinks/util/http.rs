@@ -1,23 +1,59 @@
 use super::retries::RetryLogic;
+use crate::{
+    event::{Event, Metric},
+    sources::internal_events,
+};
 use bytes::Bytes;
-use futures::{Future, Poll, Stream};
-use http::StatusCode;
+use chrono::{DateTime, Utc};
+use futures::{future, Future, Poll, Stream};
+use http::{
+    header::{LOCATION, RETRY_AFTER},
+    StatusCode, Uri,
+};
+use hyper::client::connect::dns::{GaiResolver, Name, Resolve};
+use hyper::client::connect::{Connect, Connected, Destination};
 use hyper::client::HttpConnector;
 use hyper_tls::HttpsConnector;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::executor::DefaultExecutor;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tower::Service;
 use tower_hyper::client::Client;
 use tracing::field;
 use tracing_tower_http::InstrumentedHttpService;
 
-pub type RequestBuilder = Box<dyn Fn(Vec<u8>) -> hyper::Request<Vec<u8>> + Sync + Send>;
+pub type RequestBuilder<T = Vec<u8>> = Box<dyn Fn(T) -> hyper::Request<Vec<u8>> + Sync + Send>;
 pub type Response = hyper::Response<Bytes>;
 
-#[derive(Clone)]
-pub struct HttpService {
-    inner: InstrumentedHttpService<Client<HttpsConnector<HttpConnector>, Vec<u8>>>,
-    request_builder: Arc<RequestBuilder>,
+/// Sends requests built from a `T` by the request builder. Most sinks use
+/// the encoded body itself, but a sink may carry per-request data alongside it.
+pub struct HttpService<T = Vec<u8>> {
+    inner: InnerService,
+    request_builder: Arc<RequestBuilder<T>>,
+    // The component type request phases are recorded for.
+    detailed_timing: Option<String>,
+    follow_redirects: bool,
+}
+
+type InnerService = InstrumentedHttpService<Client<HostTlsConnector, Vec<u8>>>;
+type InnerResponse = <InnerService as Service<hyper::Request<Vec<u8>>>>::Response;
+
+/// The most redirects followed for a single request.
+const MAX_REDIRECTS: usize = 5;
+
+impl<T> Clone for HttpService<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            request_builder: Arc::clone(&self.request_builder),
+            detailed_timing: self.detailed_timing.clone(),
+            follow_redirects: self.follow_redirects,
+        }
+    }
 }
 
 impl HttpService {
@@ -33,10 +69,24 @@ impl HttpService {
     }
 }
 
+/// TLS trust settings that only apply when connecting to one specific host
+#[derive(Clone)]
+pub struct HostTls {
+    pub verify_certificate: bool,
+    pub ca: Option<native_tls::Certificate>,
+}
+
 /// A builder for `HttpService`s
 pub struct HttpServiceBuilder {
     threads: usize,
     verify_certificate: bool,
+    host_tls: HashMap<String, HostTls>,
+    tls_server_name: Option<String>,
+    tls_identity: Option<native_tls::Identity>,
+    tls_ca: Option<native_tls::Certificate>,
+    detailed_timing: Option<String>,
+    follow_redirects: bool,
+    dns_last_good: Option<LastGoodAddrs>,
 }
 
 impl HttpServiceBuilder {
@@ -44,21 +94,22 @@ impl HttpServiceBuilder {
         Self {
             threads: 4,
             verify_certificate: true,
+            host_tls: HashMap::new(),
+            tls_server_name: None,
+            tls_identity: None,
+            tls_ca: None,
+            detailed_timing: None,
+            follow_redirects: false,
+            dns_last_good: None,
         }
     }
 
     /// Build the configured `HttpService`
-    pub fn build<F>(&self, request_builder: F) -> HttpService
+    pub fn build<T, F>(&self, request_builder: F) -> HttpService<T>
     where
-        F: Fn(Vec<u8>) -> hyper::Request<Vec<u8>> + Sync + Send + 'static,
+        F: Fn(T) -> hyper::Request<Vec<u8>> + Sync + Send + 'static,
     {
-        let mut http = HttpConnector::new(self.threads);
-        http.enforce_http(false);
-        let tls = native_tls::TlsConnector::builder()
-            .danger_accept_invalid_certs(!self.verify_certificate)
-            .build()
-            .expect("TLS initialization failed");
-        let https = HttpsConnector::from((http, tls));
+        let https = self.connector();
         let client = hyper::Client::builder()
             .executor(DefaultExecutor::current())
             .build(https);
@@ -66,9 +117,17 @@ impl HttpServiceBuilder {
         HttpService {
             inner,
             request_builder: Arc::new(Box::new(request_builder)),
+            detailed_timing: self.detailed_timing.clone(),
+            follow_redirects: self.follow_redirects,
         }
     }
 
+    /// Build a plain client with the same connection settings, for one-off
+    /// requests such as healthchecks
+    pub fn client(&self) -> hyper::Client<HostTlsConnector> {
+        hyper::Client::builder().build(self.connector())
+    }
+
     /// Set the number of threads used by the `HttpService`
     pub fn threads(&mut self, threads: usize) -> &mut Self {
         self.threads = threads;
@@ -80,9 +139,446 @@ impl HttpServiceBuilder {
         self.verify_certificate = verify;
         self
     }
+
+    /// Use different TLS trust settings when connecting to `host`
+    pub fn host_tls(&mut self, host: String, tls: HostTls) -> &mut Self {
+        self.host_tls.insert(host, tls);
+        self
+    }
+
+    /// Send `name` as the TLS server name (SNI) and verify the certificate
+    /// against it, whatever the host being connected to, e.g. when connecting
+    /// through a load balancer by its address
+    pub fn tls_server_name(&mut self, name: String) -> &mut Self {
+        self.tls_server_name = Some(name);
+        self
+    }
+
+    /// Present `identity` as the client certificate to every host, for
+    /// mutual TLS
+    pub fn tls_identity(&mut self, identity: native_tls::Identity) -> &mut Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+
+    /// Trust `ca` on top of the system roots for every host that doesn't have
+    /// a CA of its own
+    pub fn tls_ca(&mut self, ca: native_tls::Certificate) -> &mut Self {
+        self.tls_ca = Some(ca);
+        self
+    }
+
+    /// Record how long DNS resolution, TCP connect, the TLS handshake, the
+    /// time to first byte and the whole request take, as histogram metric
+    /// events in seconds tagged with `component_type`
+    pub fn detailed_timing(&mut self, component_type: &str) -> &mut Self {
+        self.detailed_timing = Some(component_type.into());
+        self
+    }
+
+    /// Follow up to five `301`, `302`, `307` and `308` redirects, resending
+    /// the request as is, but never from HTTPS to plain HTTP
+    pub fn follow_redirects(&mut self, follow: bool) -> &mut Self {
+        self.follow_redirects = follow;
+        self
+    }
+
+    /// Keep the addresses each host last resolved to and connect to them for
+    /// up to `ttl` after a lookup fails. Everything built by this builder
+    /// shares the addresses.
+    pub fn dns_last_good_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.dns_last_good = Some(LastGoodAddrs {
+            ttl,
+            addrs: Default::default(),
+        });
+        self
+    }
+
+    fn connector(&self) -> HostTlsConnector {
+        let hosts = self
+            .host_tls
+            .iter()
+            .map(|(host, tls)| {
+                let ca = tls.ca.clone().or_else(|| self.tls_ca.clone());
+                let connectors = self.tls_connectors(tls.verify_certificate, ca);
+                (host.clone(), connectors)
+            })
+            .collect();
+
+        HostTlsConnector {
+            default: self.tls_connectors(self.verify_certificate, self.tls_ca.clone()),
+            hosts: Arc::new(hosts),
+            detailed_timing: self.detailed_timing.clone(),
+        }
+    }
+
+    fn tls_connectors(
+        &self,
+        verify_certificate: bool,
+        ca: Option<native_tls::Certificate>,
+    ) -> TlsConnectors {
+        let http = TimedHttpConnector {
+            resolver: LastGoodResolver {
+                inner: GaiResolver::new(self.threads),
+                last_good: self.dns_last_good.clone(),
+            },
+            timings: None,
+        };
+        let mut tls = native_tls::TlsConnector::builder();
+        tls.danger_accept_invalid_certs(!verify_certificate);
+        tls.danger_accept_invalid_hostnames(!verify_certificate);
+        if let Some(ca) = ca {
+            tls.add_root_certificate(ca);
+        }
+        if let Some(identity) = &self.tls_identity {
+            tls.identity(identity.clone());
+        }
+        let tls = tls.build().expect("TLS initialization failed");
+        TlsConnectors {
+            http,
+            tls,
+            server_name: self.tls_server_name.clone(),
+        }
+    }
+}
+
+/// An `HttpsConnector` that picks its TLS settings based on the host being
+/// connected to, falling back to the default settings for unknown hosts.
+#[derive(Clone)]
+pub struct HostTlsConnector {
+    default: TlsConnectors,
+    hosts: Arc<HashMap<String, TlsConnectors>>,
+    // The component type the connection phases are recorded for.
+    detailed_timing: Option<String>,
+}
+
+/// The connectors for one set of TLS settings.
+#[derive(Clone)]
+struct TlsConnectors {
+    http: TimedHttpConnector,
+    tls: native_tls::TlsConnector,
+    server_name: Option<String>,
+}
+
+impl HostTlsConnector {
+    fn connector_for(&self, host: &str) -> &TlsConnectors {
+        self.hosts.get(host).unwrap_or(&self.default)
+    }
+}
+
+impl Connect for HostTlsConnector {
+    type Transport = HostTlsStream;
+    type Error = <HttpsConnector<TimedHttpConnector> as Connect>::Error;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let connectors = self.connector_for(dst.host());
+        // Every connection records its phases on its own, as connections to
+        // the same host may well be established at the same time.
+        let timings = self
+            .detailed_timing
+            .as_ref()
+            .map(|component_type| ConnectTimings::new(component_type));
+        let http = connectors.http.timed(timings.clone());
+        let https = dst.scheme() == "https";
+        let connecting: Self::Future = match &connectors.server_name {
+            Some(name) if https => Box::new(
+                ServerNameConnector {
+                    http,
+                    tls: connectors.tls.clone().into(),
+                    name: name.clone(),
+                }
+                .connect(dst)
+                .map(|(stream, connected)| (HostTlsStream::ServerName(stream), connected))
+                .map_err(Into::into),
+            ),
+            _ => Box::new(
+                HttpsConnector::from((http, connectors.tls.clone()))
+                    .connect(dst)
+                    .map(|(stream, connected)| (HostTlsStream::Https(stream), connected)),
+            ),
+        };
+        let timings = match timings {
+            Some(timings) => timings,
+            None => return connecting,
+        };
+
+        let start = Instant::now();
+        Box::new(connecting.inspect(move |_| {
+            // Whatever the TCP connection didn't take was spent on TLS.
+            if https {
+                let tls = start
+                    .elapsed()
+                    .checked_sub(timings.tcp())
+                    .unwrap_or_default();
+                timings.record("http_tls_handshake_duration_seconds", tls);
+            }
+        }))
+    }
+}
+
+/// Connects over TLS with a fixed server name rather than the name of the
+/// host being connected to.
+struct ServerNameConnector {
+    http: TimedHttpConnector,
+    tls: tokio_tls::TlsConnector,
+    name: String,
+}
+
+impl ServerNameConnector {
+    fn connect(
+        self,
+        dst: Destination,
+    ) -> impl Future<Item = (tokio_tls::TlsStream<TcpTransport>, Connected), Error = io::Error>
+    {
+        let tls = self.tls;
+        let name = self.name;
+        self.http.connect(dst).and_then(move |(tcp, connected)| {
+            tls.connect(&name, tcp)
+                .map(|stream| (stream, connected))
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+        })
+    }
+}
+
+type TcpTransport = <TimedHttpConnector as Connect>::Transport;
+
+/// A connection made by a `HostTlsConnector`.
+pub enum HostTlsStream {
+    Https(<HttpsConnector<TimedHttpConnector> as Connect>::Transport),
+    ServerName(tokio_tls::TlsStream<TcpTransport>),
+}
+
+impl io::Read for HostTlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            HostTlsStream::Https(stream) => stream.read(buf),
+            HostTlsStream::ServerName(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl io::Write for HostTlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            HostTlsStream::Https(stream) => stream.write(buf),
+            HostTlsStream::ServerName(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            HostTlsStream::Https(stream) => stream.flush(),
+            HostTlsStream::ServerName(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsyncRead for HostTlsStream {}
+
+impl AsyncWrite for HostTlsStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self {
+            HostTlsStream::Https(stream) => stream.shutdown(),
+            HostTlsStream::ServerName(stream) => stream.shutdown(),
+        }
+    }
+}
+
+/// The DNS and TCP connect times of one connection being established, so
+/// that each phase can be told apart from the time of the phases around it.
+#[derive(Clone)]
+struct ConnectTimings {
+    component_type: String,
+    phases: Arc<Mutex<PhaseTimes>>,
+}
+
+#[derive(Default)]
+struct PhaseTimes {
+    dns: Duration,
+    tcp: Duration,
+}
+
+impl ConnectTimings {
+    fn new(component_type: &str) -> Self {
+        Self {
+            component_type: component_type.into(),
+            phases: Default::default(),
+        }
+    }
+
+    fn dns(&self) -> Duration {
+        self.phases.lock().unwrap().dns
+    }
+
+    fn tcp(&self) -> Duration {
+        self.phases.lock().unwrap().tcp
+    }
+
+    fn record(&self, name: &str, duration: Duration) {
+        record_timing(&self.component_type, name, duration);
+    }
+}
+
+/// The addresses each host last resolved to, and when.
+#[derive(Clone)]
+struct LastGoodAddrs {
+    ttl: Duration,
+    addrs: Arc<Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>>,
+}
+
+impl LastGoodAddrs {
+    fn put(&self, host: &str, addrs: Vec<IpAddr>) {
+        let mut all = self.addrs.lock().unwrap();
+        all.insert(host.to_string(), (addrs, Instant::now()));
+    }
+
+    fn get(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let all = self.addrs.lock().unwrap();
+        all.get(host)
+            .filter(|(_, resolved)| resolved.elapsed() <= self.ttl)
+            .map(|(addrs, _)| addrs.clone())
+    }
+}
+
+/// Falls back to the addresses a host last resolved to when looking it up
+/// fails, so a brief resolver outage doesn't fail requests.
+#[derive(Clone)]
+pub struct LastGoodResolver<R = GaiResolver> {
+    inner: R,
+    last_good: Option<LastGoodAddrs>,
+}
+
+impl<R> Resolve for LastGoodResolver<R>
+where
+    R: Resolve,
+    R::Future: Send + 'static,
+{
+    type Addrs = std::vec::IntoIter<IpAddr>;
+    type Future = Box<dyn Future<Item = Self::Addrs, Error = io::Error> + Send>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let host = name.as_str().to_string();
+        let resolving = self
+            .inner
+            .resolve(name)
+            .map(|addrs| addrs.collect::<Vec<_>>());
+        let last_good = match &self.last_good {
+            Some(last_good) => last_good.clone(),
+            None => return Box::new(resolving.map(Vec::into_iter)),
+        };
+
+        Box::new(resolving.then(move |result| match result {
+            Ok(addrs) => {
+                last_good.put(&host, addrs.clone());
+                Ok(addrs.into_iter())
+            }
+            Err(error) => match last_good.get(&host) {
+                Some(addrs) => {
+                    warn!(
+                        message = "DNS resolution failed; using the last resolved addresses.",
+                        %host,
+                        %error
+                    );
+                    trace!(dns_last_good_fallback_counter = 1);
+                    Ok(addrs.into_iter())
+                }
+                None => Err(error),
+            },
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub struct TimedResolver {
+    inner: LastGoodResolver,
+    timings: Option<ConnectTimings>,
+}
+
+impl Resolve for TimedResolver {
+    type Addrs = <LastGoodResolver as Resolve>::Addrs;
+    type Future = Box<dyn Future<Item = Self::Addrs, Error = io::Error> + Send>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let timings = match &self.timings {
+            Some(timings) => timings.clone(),
+            None => return Box::new(self.inner.resolve(name)),
+        };
+
+        let start = Instant::now();
+        Box::new(self.inner.resolve(name).inspect(move |_| {
+            let dns = start.elapsed();
+            timings.phases.lock().unwrap().dns = dns;
+            timings.record("http_dns_duration_seconds", dns);
+        }))
+    }
+}
+
+/// Connects over TCP, timing the connection it makes when it has timings of
+/// its own to record them in.
+#[derive(Clone)]
+pub struct TimedHttpConnector {
+    resolver: LastGoodResolver,
+    timings: Option<ConnectTimings>,
+}
+
+impl TimedHttpConnector {
+    /// This connector, recording the phases of the next connection in
+    /// `timings`.
+    fn timed(&self, timings: Option<ConnectTimings>) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+            timings,
+        }
+    }
+}
+
+impl Connect for TimedHttpConnector {
+    type Transport = <HttpConnector<TimedResolver> as Connect>::Transport;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        // The resolver shares the connection's timings, so each connection
+        // gets an HTTP connector of its own.
+        let mut inner = HttpConnector::new_with_resolver(TimedResolver {
+            inner: self.resolver.clone(),
+            timings: self.timings.clone(),
+        });
+        inner.enforce_http(false);
+        let timings = match &self.timings {
+            Some(timings) => timings.clone(),
+            None => return Box::new(inner.connect(dst)),
+        };
+
+        let start = Instant::now();
+        Box::new(inner.connect(dst).inspect(move |_| {
+            let elapsed = start.elapsed();
+            // IP addresses are connected to without a lookup, leaving no DNS
+            // time.
+            let tcp = elapsed.checked_sub(timings.dns()).unwrap_or_default();
+            timings.phases.lock().unwrap().tcp = elapsed;
+            timings.record("http_tcp_connect_duration_seconds", tcp);
+        }))
+    }
 }
 
-impl Service<Vec<u8>> for HttpService {
+/// Records how long a phase of a request took as a histogram metric event in
+/// seconds, tagged with the type of component making the request, for
+/// `internal_events` sources to route through the topology.
+fn record_timing(component_type: &str, name: &str, duration: Duration) {
+    let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+    let tags = vec![("component_type".to_string(), component_type.to_string())];
+    internal_events::emit(Event::Metric(Metric::Histogram {
+        name: name.into(),
+        val: seconds,
+        sample_rate: 1,
+        timestamp: Some(Utc::now()),
+        tags: Some(tags.into_iter().collect()),
+    }));
+}
+
+impl<T> Service<T> for HttpService<T> {
     type Response = Response;
     type Error = hyper::Error;
     type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
@@ -91,31 +587,122 @@ impl Service<Vec<u8>> for HttpService {
         Ok(().into())
     }
 
-    fn call(&mut self, body: Vec<u8>) -> Self::Future {
+    fn call(&mut self, body: T) -> Self::Future {
         let request = (self.request_builder)(body);
 
         debug!(message = "sending request.");
 
-        let fut = self
-            .inner
-            .call(request)
-            .inspect(|res| {
+        let first_byte_timing = self.detailed_timing.clone();
+        let request_timing = self.detailed_timing.clone();
+        let redirects = if self.follow_redirects {
+            MAX_REDIRECTS
+        } else {
+            0
+        };
+        let start = Instant::now();
+        let fut = send(self.inner.clone(), request, redirects)
+            .inspect(move |res| {
                 debug!(
                     message = "response.",
                     status = &field::display(res.status()),
                     version = &field::debug(res.version()),
-                )
+                );
+                if let Some(component_type) = &first_byte_timing {
+                    let first_byte = start.elapsed();
+                    record_timing(
+                        component_type,
+                        "http_time_to_first_byte_seconds",
+                        first_byte,
+                    );
+                }
             })
             .and_then(|r| {
                 let (parts, body) = r.into_parts();
                 body.concat2()
                     .map(|b| hyper::Response::from_parts(parts, b.into_bytes()))
+            })
+            .inspect(move |_| {
+                if let Some(component_type) = &request_timing {
+                    let request = start.elapsed();
+                    record_timing(component_type, "http_request_duration_seconds", request);
+                }
             });
 
         Box::new(fut)
     }
 }
 
+/// Sends the request, following up to `redirects` redirects.
+fn send(
+    mut inner: InnerService,
+    request: hyper::Request<Vec<u8>>,
+    redirects: usize,
+) -> Box<dyn Future<Item = InnerResponse, Error = hyper::Error> + Send> {
+    if redirects == 0 {
+        return Box::new(inner.call(request));
+    }
+
+    let mut next = copy_request(&request);
+    Box::new(inner.call(request).and_then(move |response| {
+        let status = response.status();
+        match status {
+            StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT => {}
+            _ => return future::Either::A(future::ok(response)),
+        }
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| "the response has no valid Location header".to_string())
+            .and_then(|location| redirect_uri(next.uri(), location));
+        match location {
+            Ok(uri) => {
+                debug!(message = "following redirect.", %status, location = %uri);
+                *next.uri_mut() = uri;
+                future::Either::B(send(inner, next, redirects - 1))
+            }
+            Err(reason) => {
+                warn!(message = "not following redirect.", %status, %reason);
+                future::Either::A(future::ok(response))
+            }
+        }
+    }))
+}
+
+fn copy_request(request: &hyper::Request<Vec<u8>>) -> hyper::Request<Vec<u8>> {
+    let mut copy = hyper::Request::new(request.body().clone());
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.version_mut() = request.version();
+    *copy.headers_mut() = request.headers().clone();
+    copy
+}
+
+/// Resolves the `Location` of a redirect against the URI that was requested,
+/// refusing to downgrade from HTTPS to plain HTTP.
+fn redirect_uri(from: &Uri, location: &str) -> Result<Uri, String> {
+    let location = location
+        .parse::<Uri>()
+        .map_err(|error| format!("invalid Location {:?}: {}", location, error))?;
+    let uri = if location.scheme_part().is_some() {
+        location
+    } else {
+        let mut parts = location.into_parts();
+        parts.scheme = from.scheme_part().cloned();
+        parts.authority = from.authority_part().cloned();
+        Uri::from_parts(parts).map_err(|error| error.to_string())?
+    };
+
+    if from.scheme_str() == Some("https") && uri.scheme_str() != Some("https") {
+        return Err(format!("refusing to downgrade from HTTPS to {}", uri));
+    }
+    Ok(uri)
+}
+
 #[derive(Clone)]
 pub struct HttpRetryLogic;
 
@@ -133,6 +720,36 @@ impl RetryLogic for HttpRetryLogic {
         (status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED)
             || status == StatusCode::TOO_MANY_REQUESTS
     }
+
+    fn retry_after(&self, response: &Self::Response) -> Option<Duration> {
+        let status = response.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+            return None;
+        }
+
+        let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+        let wait = parse_retry_after(value, Utc::now());
+        if wait.is_none() {
+            warn!(message = "ignoring invalid Retry-After header.", %value);
+        }
+        wait
+    }
+}
+
+/// Parses a `Retry-After` header, given either as a number of seconds or as
+/// an HTTP-date. Dates in the past mean retrying right away.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.signed_duration_since(now)
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_secs(0)),
+    )
 }
 
 #[cfg(test)]
@@ -140,8 +757,10 @@ mod test {
     use super::*;
     use futures::{Future, Sink, Stream};
     use http::Method;
-    use hyper::service::service_fn;
+    use hyper::server::conn::AddrStream;
+    use hyper::service::{make_service_fn, service_fn, service_fn_ok};
     use hyper::{Body, Response, Server, Uri};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tower::Service;
 
     #[test]
@@ -159,6 +778,59 @@ mod test {
         assert!(!logic.should_retry_response(&response_501));
     }
 
+    #[test]
+    fn util_http_retry_after_in_seconds() {
+        let logic = HttpRetryLogic;
+        let response = |status, retry_after| {
+            Response::builder()
+                .status(status)
+                .header("Retry-After", retry_after)
+                .body(Bytes::new())
+                .unwrap()
+        };
+
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            logic.retry_after(&response(429, "120"))
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            logic.retry_after(&response(503, " 5 "))
+        );
+        // Only throttling responses are waited on.
+        assert_eq!(None, logic.retry_after(&response(500, "120")));
+        assert_eq!(None, logic.retry_after(&response(429, "soon")));
+        assert_eq!(
+            None,
+            logic.retry_after(&Response::builder().status(429).body(Bytes::new()).unwrap())
+        );
+    }
+
+    #[test]
+    fn util_http_retry_after_as_http_date() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            Some(Duration::from_secs(90)),
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(0)),
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now)
+        );
+        assert_eq!(None, parse_retry_after("21/10/2015 07:29:30", now));
+
+        // A date parses through the response too.
+        let response = Response::builder()
+            .status(503)
+            .header("Retry-After", "Fri, 01 Jan 2100 00:00:00 GMT")
+            .body(Bytes::new())
+            .unwrap();
+        assert!(HttpRetryLogic.retry_after(&response).unwrap() > Duration::from_secs(3600));
+    }
+
     #[test]
     fn util_http_it_makes_http_requests() {
         let addr = crate::test_util::next_addr();
@@ -211,4 +883,222 @@ mod test {
         let (body, _rest) = rx.into_future().wait().unwrap();
         assert_eq!(body.unwrap().unwrap(), "hello");
     }
+
+    #[test]
+    fn util_http_follows_redirects_only_when_enabled() {
+        let addr = crate::test_util::next_addr();
+        let new_service = make_service_fn(|_: &AddrStream| {
+            service_fn_ok(|req: hyper::Request<Body>| match req.uri().path() {
+                "/_bulk" => Response::builder()
+                    .status(307)
+                    .header("Location", "/es/_bulk")
+                    .body(Body::empty())
+                    .unwrap(),
+                _ => Response::new(Body::from(req.uri().path().to_string())),
+            })
+        });
+        let server = Server::bind(&addr)
+            .serve(new_service)
+            .map_err(|e| eprintln!("server error: {}", e));
+
+        let uri = format!("http://{}/_bulk", addr).parse::<Uri>().unwrap();
+        let build = move |follow: bool| {
+            let uri = uri.clone();
+            HttpService::builder()
+                .follow_redirects(follow)
+                .build(move |body: Vec<u8>| {
+                    let mut builder = hyper::Request::builder();
+                    builder.method(Method::POST);
+                    builder.uri(uri.clone());
+                    builder.body(body).unwrap()
+                })
+        };
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+
+        let mut following = build(true);
+        let response = rt.block_on(following.call(b"hello".to_vec())).unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(&b"/es/_bulk"[..], &response.body()[..]);
+
+        let mut not_following = build(false);
+        let response = rt.block_on(not_following.call(b"hello".to_vec())).unwrap();
+        assert_eq!(307, response.status().as_u16());
+
+        rt.shutdown_now();
+    }
+
+    #[test]
+    fn util_http_resolves_redirects_without_downgrading() {
+        let from = "https://es.example.com/_bulk".parse::<Uri>().unwrap();
+        assert_eq!(
+            "https://es.example.com/es/_bulk",
+            redirect_uri(&from, "/es/_bulk").unwrap().to_string()
+        );
+        assert_eq!(
+            "https://other.example.com/_bulk",
+            redirect_uri(&from, "https://other.example.com/_bulk")
+                .unwrap()
+                .to_string()
+        );
+        assert!(redirect_uri(&from, "http://es.example.com/_bulk").is_err());
+
+        let from = "http://es.example.com/_bulk".parse::<Uri>().unwrap();
+        assert!(redirect_uri(&from, "https://es.example.com/_bulk").is_ok());
+    }
+
+    #[test]
+    fn util_http_host_tls_applies_only_to_configured_host() {
+        let mut builder = HttpService::builder();
+        builder.host_tls(
+            "dev-cluster.local".into(),
+            HostTls {
+                verify_certificate: false,
+                ca: None,
+            },
+        );
+        let connector = builder.connector();
+
+        assert!(!std::ptr::eq(
+            connector.connector_for("dev-cluster.local"),
+            &connector.default
+        ));
+        assert!(std::ptr::eq(
+            connector.connector_for("prod-cluster.local"),
+            &connector.default
+        ));
+    }
+
+    #[test]
+    fn util_http_tls_server_name_applies_to_every_host() {
+        let mut builder = HttpService::builder();
+        builder.host_tls(
+            "10.0.0.5".into(),
+            HostTls {
+                verify_certificate: false,
+                ca: None,
+            },
+        );
+        builder.tls_server_name("es.internal".into());
+        let connector = builder.connector();
+
+        for host in &["10.0.0.5", "10.0.0.6"] {
+            let server_name = connector.connector_for(host).server_name.as_ref();
+            assert_eq!(Some("es.internal"), server_name.map(String::as_str));
+        }
+
+        let connector = HttpService::builder().connector();
+        assert!(connector.connector_for("10.0.0.5").server_name.is_none());
+    }
+
+    #[test]
+    fn util_http_records_detailed_timing() {
+        let addr = crate::test_util::next_addr();
+        let uri = format!("http://localhost:{}/", addr.port())
+            .parse::<Uri>()
+            .unwrap();
+
+        let mut service = HttpService::builder()
+            .detailed_timing("util_http_timing_test")
+            .build(move |body| {
+                let mut builder = hyper::Request::builder();
+                builder.method(Method::POST);
+                builder.uri(uri.clone());
+                builder.body(body).unwrap()
+            });
+
+        let new_service = make_service_fn(|_: &AddrStream| {
+            service_fn_ok(|_: hyper::Request<Body>| Response::new(Body::from("ok")))
+        });
+        let server = Server::bind(&addr)
+            .serve(new_service)
+            .map_err(|e| eprintln!("server error: {}", e));
+
+        let events = internal_events::subscribe();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        rt.block_on(service.call(b"hello".to_vec())).unwrap();
+
+        // Other tests may emit events of their own meanwhile, so only the
+        // histograms tagged as this test's are counted.
+        let names = events
+            .filter_map(|event| match event {
+                Event::Metric(Metric::Histogram { name, tags, .. }) => {
+                    let component_type = tags?.remove("component_type")?;
+                    if component_type == "util_http_timing_test" {
+                        Some(name)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .take(4)
+            .collect();
+        let names = rt.block_on(names).unwrap();
+        // Plain HTTP has no handshake to time.
+        assert_eq!(
+            vec![
+                "http_dns_duration_seconds",
+                "http_tcp_connect_duration_seconds",
+                "http_time_to_first_byte_seconds",
+                "http_request_duration_seconds",
+            ],
+            names
+        );
+    }
+
+    #[derive(Clone)]
+    struct FlakyResolver(Arc<AtomicUsize>);
+
+    impl Resolve for FlakyResolver {
+        type Addrs = std::vec::IntoIter<IpAddr>;
+        type Future = futures::future::FutureResult<Self::Addrs, io::Error>;
+
+        // Only the first lookup succeeds.
+        fn resolve(&self, _: Name) -> Self::Future {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                futures::future::ok(vec!["10.0.0.1".parse().unwrap()].into_iter())
+            } else {
+                futures::future::err(io::Error::new(io::ErrorKind::Other, "resolver down"))
+            }
+        }
+    }
+
+    fn flaky_resolver(last_good_ttl: Option<Duration>) -> LastGoodResolver<FlakyResolver> {
+        LastGoodResolver {
+            inner: FlakyResolver(Arc::new(AtomicUsize::new(0))),
+            last_good: last_good_ttl.map(|ttl| LastGoodAddrs {
+                ttl,
+                addrs: Default::default(),
+            }),
+        }
+    }
+
+    #[test]
+    fn util_http_uses_last_good_addresses_when_dns_fails() {
+        let name = || "es.example.com".parse::<Name>().unwrap();
+        let resolve = |resolver: &LastGoodResolver<FlakyResolver>, name| {
+            resolver
+                .resolve(name)
+                .wait()
+                .map(|addrs| addrs.collect::<Vec<_>>())
+        };
+        let addr = "10.0.0.1".parse::<IpAddr>().unwrap();
+
+        let resolver = flaky_resolver(Some(Duration::from_secs(60)));
+        assert_eq!(vec![addr], resolve(&resolver, name()).unwrap());
+        assert_eq!(vec![addr], resolve(&resolver, name()).unwrap());
+        assert!(resolve(&resolver, "other.example.com".parse().unwrap()).is_err());
+
+        let resolver = flaky_resolver(None);
+        assert_eq!(vec![addr], resolve(&resolver, name()).unwrap());
+        assert!(resolve(&resolver, name()).is_err());
+
+        let resolver = flaky_resolver(Some(Duration::from_millis(10)));
+        assert_eq!(vec![addr], resolve(&resolver, name()).unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(resolve(&resolver, name()).is_err());
+    }
 }
@@ -0,0 +1,386 @@
+//! Validation of documents against the commonly used subset of JSON Schema:
+//! `type`, `enum`, `properties`, `required`, `additionalProperties`, `items`,
+//! `minimum`, `maximum`, `minLength`, `maxLength` and `pattern`. Any other
+//! keyword is ignored.
+
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+const TYPES: &[&str] = &[
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+#[derive(Debug)]
+pub struct JsonSchema {
+    root: Schema,
+}
+
+impl JsonSchema {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read(path)
+            .map_err(|error| format!("Could not read schema {:?}: {}", path, error))?;
+        let value = serde_json::from_slice(&contents)
+            .map_err(|error| format!("Could not parse schema {:?}: {}", path, error))?;
+        Self::from_value(&value).map_err(|error| format!("Invalid schema {:?}: {}", path, error))
+    }
+
+    pub fn from_value(value: &Value) -> Result<Self, String> {
+        Ok(Self {
+            root: Schema::compile(value, "")?,
+        })
+    }
+
+    /// Returns the first violation found, if any.
+    pub fn validate(&self, document: &Value) -> Result<(), String> {
+        self.root.validate(document, "")
+    }
+}
+
+#[derive(Debug, Default)]
+struct Schema {
+    reject_all: bool,
+    types: Option<Vec<String>>,
+    enumeration: Option<Vec<Value>>,
+    properties: Vec<(String, Schema)>,
+    required: Vec<String>,
+    additional_properties: Option<Box<Schema>>,
+    items: Option<Box<Schema>>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    min_length: Option<u64>,
+    max_length: Option<u64>,
+    pattern: Option<Regex>,
+}
+
+impl Schema {
+    fn compile(value: &Value, path: &str) -> Result<Self, String> {
+        let object = match value {
+            Value::Bool(allow) => {
+                return Ok(Self {
+                    reject_all: !allow,
+                    ..Default::default()
+                })
+            }
+            Value::Object(object) => object,
+            _ => return Err(format!("{} must be an object or a boolean", at(path))),
+        };
+
+        let mut schema = Self::default();
+
+        if let Some(types) = object.get("type") {
+            let types = match types {
+                Value::String(name) => vec![name.clone()],
+                Value::Array(names) => names
+                    .iter()
+                    .map(|name| name.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| format!("{}/type must only contain strings", path))?,
+                _ => return Err(format!("{}/type must be a string or an array", path)),
+            };
+            if let Some(unknown) = types.iter().find(|name| !TYPES.contains(&name.as_str())) {
+                return Err(format!("{}/type has unknown type {:?}", path, unknown));
+            }
+            schema.types = Some(types);
+        }
+
+        if let Some(values) = object.get("enum") {
+            let values = values
+                .as_array()
+                .ok_or_else(|| format!("{}/enum must be an array", path))?;
+            schema.enumeration = Some(values.clone());
+        }
+
+        if let Some(properties) = object.get("properties") {
+            let properties = properties
+                .as_object()
+                .ok_or_else(|| format!("{}/properties must be an object", path))?;
+            for (name, property) in properties {
+                let property = Self::compile(property, &format!("{}/properties/{}", path, name))?;
+                schema.properties.push((name.clone(), property));
+            }
+        }
+
+        if let Some(required) = object.get("required") {
+            schema.required = required
+                .as_array()
+                .and_then(|names| {
+                    names
+                        .iter()
+                        .map(|name| name.as_str().map(String::from))
+                        .collect()
+                })
+                .ok_or_else(|| format!("{}/required must be an array of strings", path))?;
+        }
+
+        if let Some(additional) = object.get("additionalProperties") {
+            let additional = Self::compile(additional, &format!("{}/additionalProperties", path))?;
+            schema.additional_properties = Some(Box::new(additional));
+        }
+
+        if let Some(items) = object.get("items") {
+            if items.is_array() {
+                return Err(format!("{}/items as an array is not supported", path));
+            }
+            schema.items = Some(Box::new(Self::compile(items, &format!("{}/items", path))?));
+        }
+
+        schema.minimum = number(object, "minimum", path)?;
+        schema.maximum = number(object, "maximum", path)?;
+        schema.min_length = length(object, "minLength", path)?;
+        schema.max_length = length(object, "maxLength", path)?;
+
+        if let Some(pattern) = object.get("pattern") {
+            let pattern = pattern
+                .as_str()
+                .ok_or_else(|| format!("{}/pattern must be a string", path))?;
+            let pattern = Regex::new(pattern)
+                .map_err(|error| format!("{}/pattern is invalid: {}", path, error))?;
+            schema.pattern = Some(pattern);
+        }
+
+        Ok(schema)
+    }
+
+    fn validate(&self, value: &Value, path: &str) -> Result<(), String> {
+        if self.reject_all {
+            return Err(format!("{} is not allowed", at(path)));
+        }
+
+        if let Some(types) = &self.types {
+            if !types.iter().any(|name| is_type(value, name)) {
+                return Err(format!(
+                    "{} should be of type {}, got {}",
+                    at(path),
+                    types.join(" or "),
+                    value
+                ));
+            }
+        }
+
+        if let Some(values) = &self.enumeration {
+            if !values.contains(value) {
+                return Err(format!(
+                    "{} should be one of {}, got {}",
+                    at(path),
+                    Value::Array(values.clone()),
+                    value
+                ));
+            }
+        }
+
+        match value {
+            Value::Object(object) => {
+                for name in &self.required {
+                    if !object.contains_key(name) {
+                        return Err(format!("{} is missing required field {:?}", at(path), name));
+                    }
+                }
+                for (name, value) in object {
+                    let path = format!("{}/{}", path, name);
+                    match self
+                        .properties
+                        .iter()
+                        .find(|(property, _)| property == name)
+                    {
+                        Some((_, schema)) => schema.validate(value, &path)?,
+                        None => {
+                            if let Some(additional) = &self.additional_properties {
+                                additional.validate(value, &path)?;
+                            }
+                        }
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(schema) = &self.items {
+                    for (i, item) in items.iter().enumerate() {
+                        schema.validate(item, &format!("{}/{}", path, i))?;
+                    }
+                }
+            }
+            Value::Number(number) => {
+                let number = number.as_f64().unwrap_or(0.0);
+                if self.minimum.map_or(false, |minimum| number < minimum) {
+                    return Err(format!(
+                        "{} is below the minimum of {}",
+                        at(path),
+                        self.minimum.unwrap()
+                    ));
+                }
+                if self.maximum.map_or(false, |maximum| number > maximum) {
+                    return Err(format!(
+                        "{} is above the maximum of {}",
+                        at(path),
+                        self.maximum.unwrap()
+                    ));
+                }
+            }
+            Value::String(string) => {
+                let len = string.chars().count() as u64;
+                if self.min_length.map_or(false, |min| len < min) {
+                    return Err(format!(
+                        "{} is shorter than {} characters",
+                        at(path),
+                        self.min_length.unwrap()
+                    ));
+                }
+                if self.max_length.map_or(false, |max| len > max) {
+                    return Err(format!(
+                        "{} is longer than {} characters",
+                        at(path),
+                        self.max_length.unwrap()
+                    ));
+                }
+                if let Some(pattern) = &self.pattern {
+                    if !pattern.is_match(string) {
+                        return Err(format!(
+                            "{} does not match {:?}",
+                            at(path),
+                            pattern.as_str()
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn at(path: &str) -> &str {
+    if path.is_empty() {
+        "document"
+    } else {
+        path
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("null", Value::Null) => true,
+        ("boolean", Value::Bool(_)) => true,
+        ("object", Value::Object(_)) => true,
+        ("array", Value::Array(_)) => true,
+        ("number", Value::Number(_)) => true,
+        ("integer", Value::Number(number)) => {
+            number.is_i64()
+                || number.is_u64()
+                || number.as_f64().map_or(false, |f| f.fract() == 0.0)
+        }
+        ("string", Value::String(_)) => true,
+        _ => false,
+    }
+}
+
+fn number(object: &Map<String, Value>, keyword: &str, path: &str) -> Result<Option<f64>, String> {
+    match object.get(keyword) {
+        None => Ok(None),
+        Some(value) => value
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| format!("{}/{} must be a number", path, keyword)),
+    }
+}
+
+fn length(object: &Map<String, Value>, keyword: &str, path: &str) -> Result<Option<u64>, String> {
+    match object.get(keyword) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("{}/{} must be a non-negative integer", path, keyword)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonSchema;
+    use serde_json::json;
+
+    fn schema() -> JsonSchema {
+        JsonSchema::from_value(&json!({
+            "type": "object",
+            "required": ["message", "status"],
+            "properties": {
+                "message": { "type": "string", "minLength": 1 },
+                "status": { "type": "integer", "minimum": 100, "maximum": 599 },
+                "level": { "enum": ["debug", "info", "warn", "error"] },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "http": {
+                    "type": "object",
+                    "properties": { "path": { "type": "string", "pattern": "^/" } },
+                    "additionalProperties": false
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_conforming_documents() {
+        let schema = schema();
+
+        assert!(schema
+            .validate(&json!({ "message": "hi", "status": 200 }))
+            .is_ok());
+        assert!(schema
+            .validate(&json!({
+                "message": "hi",
+                "status": 404.0,
+                "level": "warn",
+                "tags": ["a", "b"],
+                "http": { "path": "/index.html" },
+                "unlisted": { "anything": [1, 2] }
+            }))
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_non_conforming_documents() {
+        let schema = schema();
+        let error = |document| schema.validate(&document).unwrap_err();
+
+        assert_eq!(
+            "document is missing required field \"status\"",
+            error(json!({ "message": "hi" }))
+        );
+        assert_eq!(
+            "/status should be of type integer, got \"200\"",
+            error(json!({ "message": "hi", "status": "200" }))
+        );
+        assert_eq!(
+            "/status is above the maximum of 599",
+            error(json!({ "message": "hi", "status": 600 }))
+        );
+        assert_eq!(
+            "/message is shorter than 1 characters",
+            error(json!({ "message": "", "status": 200 }))
+        );
+        assert_eq!(
+            "/tags/1 should be of type string, got 2",
+            error(json!({ "message": "hi", "status": 200, "tags": ["a", 2] }))
+        );
+        assert_eq!(
+            "/http/host is not allowed",
+            error(json!({ "message": "hi", "status": 200, "http": { "host": "a" } }))
+        );
+        assert_eq!(
+            "/http/path does not match \"^/\"",
+            error(json!({ "message": "hi", "status": 200, "http": { "path": "x" } }))
+        );
+        assert!(
+            error(json!({ "message": "hi", "status": 200, "level": "trace" }))
+                .starts_with("/level should be one of")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_schemas() {
+        assert!(JsonSchema::from_value(&json!({ "type": "text" })).is_err());
+        assert!(JsonSchema::from_value(&json!({ "required": "message" })).is_err());
+        assert!(JsonSchema::from_value(&json!({ "pattern": "(" })).is_err());
+        assert!(JsonSchema::from_value(&json!("object")).is_err());
+    }
+}
@@ -1,20 +1,27 @@
 pub mod batch;
 pub mod buffer;
 pub mod http;
+pub mod json_schema;
 pub mod partition;
 pub mod retries;
+pub mod shared;
+pub mod state;
 
 use crate::buffers::Acker;
 use futures::{
     future, stream::FuturesUnordered, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
 };
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::time::Duration;
 use tower::Service;
 
 pub use batch::{Batch, BatchSink};
-pub use buffer::{Buffer, Compression, PartitionBuffer, PartitionInnerBuffer};
+pub use buffer::{
+    BoundaryBuffer, Buffer, Compression, CountedBuffer, DistinctKeysBuffer, PartitionBuffer,
+    PartitionInnerBuffer,
+};
 pub use partition::{Partition, PartitionedBatchSink};
 
 pub trait SinkExt<T>
@@ -101,6 +108,63 @@ impl<T: Sink> Sink for StreamAck<T> {
     }
 }
 
+/// What a sink does with events handed to it after it has started closing.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PostShutdownPolicy {
+    /// Include the events in the final flush.
+    AcceptUntilFlush,
+    /// Drop the events without acking them, so a disk buffer replays them on
+    /// the next start.
+    Reject,
+}
+
+pub struct PostShutdown<T> {
+    inner: T,
+    policy: PostShutdownPolicy,
+    closing: bool,
+}
+
+impl<T: Sink> PostShutdown<T> {
+    pub fn new(inner: T, policy: PostShutdownPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            closing: false,
+        }
+    }
+}
+
+impl<T: Sink> Sink for PostShutdown<T> {
+    type SinkItem = T::SinkItem;
+    type SinkError = T::SinkError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.closing && self.policy == PostShutdownPolicy::Reject {
+            warn!(
+                message = "rejecting event received after shutdown began.",
+                rejected_after_shutdown_counter = 1
+            );
+            return Ok(AsyncSink::Ready);
+        }
+
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        if self.closing {
+            self.inner.close()
+        } else {
+            self.inner.poll_complete()
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.closing = true;
+        self.inner.close()
+    }
+}
+
 pub type MetadataFuture<F, M> = future::Join<F, future::FutureResult<M, <F as Future>::Error>>;
 
 pub struct BatchServiceSink<T, S: Service<T>, B: Batch<Output = T>> {
@@ -152,6 +216,7 @@ where
                     let items_in_batch = batch.num_items();
                     let seqno = self.seq_head;
                     self.seq_head += 1;
+                    self.acker.sent(items_in_batch);
                     self.in_flight.push(
                         self.service
                             .call(batch.finish())
@@ -209,10 +274,10 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::BatchServiceSink;
+    use super::{BatchServiceSink, PostShutdown, PostShutdownPolicy};
     use crate::buffers::Acker;
     use crate::test_util::wait_for;
-    use futures::{stream, sync::oneshot, Future, Poll, Sink};
+    use futures::{stream, sync::oneshot, AsyncSink, Future, Poll, Sink};
     use std::sync::{atomic::Ordering, Arc, Mutex};
     use tokio::runtime::Runtime;
     use tower::Service;
@@ -308,4 +373,55 @@ mod test {
         std::thread::sleep(std::time::Duration::from_millis(50));
         assert_eq!(15, ack_counter.load(Ordering::Relaxed));
     }
+
+    #[test]
+    fn batch_service_sink_tracks_pending_events() {
+        let mut rt = Runtime::new().unwrap();
+
+        let (service, senders) = FakeService::new();
+        let (acker, _) = Acker::new_for_testing();
+        let pending = acker.clone();
+
+        let service_sink = BatchServiceSink::new(service, acker);
+        let batches = vec![vec![(); 1], vec![(); 2], vec![(); 4]];
+        rt.spawn(service_sink.send_all(stream::iter_ok(batches)).map(|_| ()));
+
+        wait_for(|| senders.lock().unwrap().len() == 3);
+        assert_eq!(7, pending.pending());
+
+        senders.lock().unwrap().remove(0).send(()).unwrap(); // 1
+        wait_for(|| pending.pending() == 6);
+
+        // Acked in order, so the third batch waits for the second.
+        senders.lock().unwrap().remove(1).send(()).unwrap(); // 4
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(6, pending.pending());
+
+        senders.lock().unwrap().remove(0).send(()).unwrap(); // 2
+        wait_for(|| pending.pending() == 0);
+    }
+
+    #[test]
+    fn post_shutdown_accept_until_flush() {
+        let mut sink = PostShutdown::new(Vec::new(), PostShutdownPolicy::AcceptUntilFlush);
+
+        assert_eq!(Ok(AsyncSink::Ready), sink.start_send(1));
+        sink.close().unwrap();
+        assert_eq!(Ok(AsyncSink::Ready), sink.start_send(2));
+        sink.close().unwrap();
+
+        assert_eq!(vec![1, 2], sink.inner);
+    }
+
+    #[test]
+    fn post_shutdown_reject() {
+        let mut sink = PostShutdown::new(Vec::new(), PostShutdownPolicy::Reject);
+
+        assert_eq!(Ok(AsyncSink::Ready), sink.start_send(1));
+        sink.close().unwrap();
+        assert_eq!(Ok(AsyncSink::Ready), sink.start_send(2));
+        sink.close().unwrap();
+
+        assert_eq!(vec![1], sink.inner);
+    }
 }
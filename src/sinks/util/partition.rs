@@ -1,4 +1,4 @@
-use crate::sinks::util::Batch;
+use crate::sinks::util::{batch::linger_deadline, Batch};
 use futures::{
     future::Either, stream::FuturesUnordered, sync::oneshot, Async, AsyncSink, Future, Poll, Sink,
     StartSend, Stream,
@@ -7,7 +7,7 @@ use std::{
     collections::{HashMap, VecDeque},
     fmt,
     hash::Hash,
-    time::{Duration, Instant},
+    time::Duration,
 };
 use tokio::timer::Delay;
 
@@ -32,6 +32,7 @@ pub struct PartitionedBatchSink<B, S, K> {
 #[derive(Copy, Debug, Clone)]
 struct Config {
     max_linger: Option<Duration>,
+    aligned_linger: bool,
     max_size: usize,
     min_size: usize,
 }
@@ -48,6 +49,7 @@ where
     pub fn new(sink: S, batch: B, max_size: usize) -> Self {
         let config = Config {
             max_linger: None,
+            aligned_linger: false,
             max_size: max_size,
             min_size: 0,
         };
@@ -74,6 +76,7 @@ where
     ) -> Self {
         let config = Config {
             max_linger: Some(linger),
+            aligned_linger: false,
             max_size,
             min_size,
         };
@@ -90,6 +93,12 @@ where
         }
     }
 
+    /// Flush lingering partitions on wall clock multiples of the linger time
+    pub fn aligned_linger(mut self, aligned: bool) -> Self {
+        self.config.aligned_linger = aligned;
+        self
+    }
+
     pub fn into_inner_sink(self) -> S {
         self.sink
     }
@@ -99,7 +108,8 @@ where
             let (tx, rx) = oneshot::channel();
             let partition_clone = partition.clone();
 
-            let delay = Delay::new(Instant::now() + max_linger)
+            let deadline = linger_deadline(max_linger, self.config.aligned_linger);
+            let delay = Delay::new(deadline)
                 .map(move |_| LingerState::Elapsed(partition_clone))
                 .map_err(|_| ());
 
@@ -144,11 +154,11 @@ where
         let partition = item.partition();
 
         if let Some(batch) = self.partitions.get_mut(&partition) {
-            if batch.len() >= self.config.max_size {
+            if batch.len() >= self.config.max_size || batch.should_flush() {
                 self.poll_complete()?;
 
                 if let Some(batch) = self.partitions.get_mut(&partition) {
-                    if batch.len() >= self.config.max_size {
+                    if batch.len() >= self.config.max_size || batch.should_flush() {
                         return Ok(AsyncSink::NotReady(item));
                     } else {
                         batch.push(item);
@@ -204,7 +214,9 @@ where
         let ready = self
             .partitions
             .iter()
-            .filter(|(_, b)| closing || (b.len() >= max_size || b.len() >= min_size))
+            .filter(|(_, b)| {
+                closing || b.len() >= max_size || b.len() >= min_size || b.should_flush()
+            })
             .map(|(p, _)| p.clone())
             .collect::<Vec<_>>();
 
@@ -1,5 +1,10 @@
 use super::Error;
 use futures::{try_ready, Async, Future, Poll};
+use rand::Rng;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 use std::time::{Duration, Instant};
 use tokio::timer::Delay;
 use tower::{retry::Policy, timeout::error::Elapsed};
@@ -13,15 +18,50 @@ pub trait RetryLogic: Clone {
     fn should_retry_response(&self, _response: &Self::Response) -> bool {
         false
     }
+
+    /// Caps the retries made for a response below the policy's attempts,
+    /// counting the retries already made for any reason.
+    fn response_retry_limit(&self, _response: &Self::Response) -> Option<usize> {
+        None
+    }
+
+    /// The least time to wait before retrying a response, e.g. as asked for
+    /// by the service. Longer than the policy's backoff, it replaces it.
+    fn retry_after(&self, _response: &Self::Response) -> Option<Duration> {
+        None
+    }
+
+    /// Whether to retry requests that timed out, which the service may have
+    /// handled all the same.
+    fn is_retriable_timeout(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FixedRetryPolicy<L: RetryLogic> {
     remaining_attempts: usize,
+    attempts: usize,
     backoff: Duration,
+    max_backoff: Option<Duration>,
+    jitter: bool,
     logic: L,
+    budget: Option<RetryBudget>,
+    permit: Option<Arc<RetryPermit>>,
 }
 
+/// Caps how many requests may be retrying at once, so that retries can't
+/// crowd fresh requests out of the in-flight limit.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    in_use: Arc<AtomicUsize>,
+    max: usize,
+}
+
+// Held by a request from its first retry until it completes.
+#[derive(Debug)]
+struct RetryPermit(Arc<AtomicUsize>);
+
 pub struct RetryPolicyFuture<L: RetryLogic> {
     delay: Delay,
     policy: FixedRetryPolicy<L>,
@@ -31,22 +71,141 @@ impl<L: RetryLogic> FixedRetryPolicy<L> {
     pub fn new(remaining_attempts: usize, backoff: Duration, logic: L) -> Self {
         FixedRetryPolicy {
             remaining_attempts,
+            attempts: 0,
             backoff,
+            max_backoff: None,
+            jitter: false,
             logic,
+            budget: None,
+            permit: None,
         }
     }
 
-    fn build_retry(&self) -> RetryPolicyFuture<L> {
-        let policy = FixedRetryPolicy::new(
-            self.remaining_attempts - 1,
-            self.backoff.clone(),
-            self.logic.clone(),
-        );
-        let next = Instant::now() + self.backoff;
-        let delay = Delay::new(next);
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Double the backoff after every retry, up to `max`, so a struggling
+    /// service gets more and more room to recover
+    pub fn with_exponential_backoff(mut self, max: Duration) -> Self {
+        self.max_backoff = Some(max);
+        self
+    }
+
+    /// Wait a random time between zero and the backoff before each retry
+    /// (full jitter), so that many clients failing at once don't all retry at
+    /// the same moment
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// How long to wait before the next retry.
+    fn next_backoff(&self) -> Duration {
+        if self.jitter {
+            jitter(self.delay())
+        } else {
+            self.delay()
+        }
+    }
+
+    /// The backoff before the next retry, before any jitter.
+    fn delay(&self) -> Duration {
+        let max = match self.max_backoff {
+            Some(max) => max,
+            None => return self.backoff,
+        };
+        2u32.checked_pow(self.attempts as u32)
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .map_or(max, |delay| std::cmp::min(delay, max))
+    }
+
+    fn build_retry(&self) -> Option<RetryPolicyFuture<L>> {
+        self.build_retry_after(None)
+    }
+
+    fn build_retry_after(&self, min_backoff: Option<Duration>) -> Option<RetryPolicyFuture<L>> {
+        let permit = match (&self.permit, &self.budget) {
+            (Some(permit), _) => Some(Arc::clone(permit)),
+            (None, Some(budget)) => match budget.acquire() {
+                Some(permit) => Some(Arc::new(permit)),
+                None => {
+                    error!(
+                        message = "retry budget exhausted; not retrying request.",
+                        retry_budget_exhausted_counter = 1
+                    );
+                    return None;
+                }
+            },
+            (None, None) => None,
+        };
+
+        let policy = FixedRetryPolicy {
+            remaining_attempts: self.remaining_attempts - 1,
+            attempts: self.attempts + 1,
+            backoff: self.backoff,
+            max_backoff: self.max_backoff,
+            jitter: self.jitter,
+            logic: self.logic.clone(),
+            budget: self.budget.clone(),
+            permit,
+        };
+        let backoff = match min_backoff {
+            Some(min) => std::cmp::max(min, self.next_backoff()),
+            None => self.next_backoff(),
+        };
+        let delay = Delay::new(Instant::now() + backoff);
+
+        debug!(message = "retrying request.", delay_ms = %backoff.as_millis());
+        Some(RetryPolicyFuture { delay, policy })
+    }
+}
+
+fn jitter(backoff: Duration) -> Duration {
+    let millis = backoff.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(0, millis + 1))
+}
+
+impl RetryBudget {
+    pub fn new(max: usize) -> Self {
+        Self {
+            in_use: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// The number of requests currently retrying
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::SeqCst)
+    }
+
+    /// Whether every permit is taken, so failed requests aren't retried
+    pub fn is_exhausted(&self) -> bool {
+        self.in_use() >= self.max
+    }
+
+    fn acquire(&self) -> Option<RetryPermit> {
+        let mut current = self.in_use.load(Ordering::SeqCst);
+        loop {
+            if current >= self.max {
+                return None;
+            }
+
+            let previous = self
+                .in_use
+                .compare_and_swap(current, current + 1, Ordering::SeqCst);
+            if previous == current {
+                return Some(RetryPermit(Arc::clone(&self.in_use)));
+            }
+            current = previous;
+        }
+    }
+}
 
-        debug!(message = "retrying request.", delay_ms = %self.backoff.as_millis());
-        RetryPolicyFuture { delay, policy }
+impl Drop for RetryPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -66,8 +225,14 @@ where
                 }
 
                 if self.logic.should_retry_response(response) {
+                    if let Some(limit) = self.logic.response_retry_limit(response) {
+                        if self.attempts >= limit {
+                            error!(message = "retries exhausted for response.", limit);
+                            return None;
+                        }
+                    }
                     warn!(message = "retrying after response.");
-                    Some(self.build_retry())
+                    self.build_retry_after(self.logic.retry_after(response))
                 } else {
                     None
                 }
@@ -81,14 +246,19 @@ where
                 if let Some(expected) = error.downcast_ref::<L::Error>() {
                     if self.logic.is_retriable_error(expected) {
                         warn!("retrying after error: {}", expected);
-                        Some(self.build_retry())
+                        self.build_retry()
                     } else {
                         error!(message = "encountered non-retriable error.", %error);
                         None
                     }
                 } else if let Some(_) = error.downcast_ref::<Elapsed>() {
-                    warn!("request timedout.");
-                    Some(self.build_retry())
+                    if self.logic.is_retriable_timeout() {
+                        warn!("request timedout.");
+                        self.build_retry()
+                    } else {
+                        error!("request timedout; not retrying.");
+                        None
+                    }
                 } else {
                     warn!(message = "unexpected error type.", %error);
                     None
@@ -189,6 +359,115 @@ mod tests {
         });
     }
 
+    #[test]
+    fn exponential_backoff_doubles_up_to_the_cap() {
+        clock::mock(|_| {
+            let mut policy = FixedRetryPolicy::new(5, Duration::from_secs(1), SvcRetryLogic)
+                .with_exponential_backoff(Duration::from_secs(5));
+
+            let mut delays = Vec::new();
+            while policy.remaining_attempts > 0 {
+                delays.push(policy.delay().as_secs());
+                policy = policy.build_retry().unwrap().policy;
+            }
+            assert_eq!(vec![1, 2, 4, 5, 5], delays);
+
+            // Without a cap the backoff stays fixed.
+            let policy = FixedRetryPolicy::new(5, Duration::from_secs(1), SvcRetryLogic);
+            let policy = policy.build_retry().unwrap().policy;
+            assert_eq!(Duration::from_secs(1), policy.delay());
+        });
+    }
+
+    #[test]
+    fn jitter_randomizes_delays_only_when_enabled() {
+        clock::mock(|_| {
+            let backoffs = |jitter| {
+                let mut policy = FixedRetryPolicy::new(5, Duration::from_secs(1), SvcRetryLogic)
+                    .with_exponential_backoff(Duration::from_secs(5))
+                    .with_jitter(jitter);
+                let mut backoffs = Vec::new();
+                while policy.remaining_attempts > 0 {
+                    backoffs.push((policy.next_backoff(), policy.delay()));
+                    policy = policy.build_retry().unwrap().policy;
+                }
+                backoffs
+            };
+
+            // Without jitter the delays are exactly the computed backoff.
+            let fixed = backoffs(false);
+            assert_eq!(
+                vec![1, 2, 4, 5, 5],
+                fixed
+                    .iter()
+                    .map(|(backoff, _)| backoff.as_secs())
+                    .collect::<Vec<_>>()
+            );
+            assert!(fixed.iter().all(|(backoff, delay)| backoff == delay));
+
+            assert!(backoffs(true)
+                .iter()
+                .all(|(backoff, delay)| backoff <= delay));
+        });
+    }
+
+    #[test]
+    fn retry_after_overrides_a_shorter_backoff() {
+        let policy = FixedRetryPolicy::new(5, Duration::from_secs(1), SvcRetryLogic);
+        // Whole seconds until the retry, rounded to absorb the time spent here.
+        let wait = |retry: Option<RetryPolicyFuture<_>>| {
+            let remaining = retry.unwrap().delay.deadline() - Instant::now();
+            (remaining + Duration::from_millis(500)).as_secs()
+        };
+
+        assert_eq!(10, wait(policy.retry(&"hello", Ok(&"busy"))));
+        // A shorter wait than the backoff doesn't shorten it.
+        assert_eq!(
+            1,
+            wait(policy.build_retry_after(Some(Duration::from_millis(10))))
+        );
+    }
+
+    #[test]
+    fn retry_budget_leaves_room_for_fresh_requests() {
+        clock::mock(|clock| {
+            trace_init();
+
+            let budget = RetryBudget::new(1);
+            let policy = FixedRetryPolicy::new(5, Duration::from_secs(1), SvcRetryLogic)
+                .with_budget(budget.clone());
+
+            let (service, mut handle) = mock::pair();
+            let mut svc = Retry::new(policy, service);
+
+            assert_ready!(svc.poll_ready());
+            let mut first = svc.call("first");
+            assert_request_eq!(handle, "first").send_error(Error(true));
+            assert_not_ready!(first.poll());
+            assert_eq!(1, budget.in_use());
+
+            // The budget is spent, so this failure is not retried and its
+            // slot is freed up immediately.
+            assert_ready!(svc.poll_ready());
+            let mut second = svc.call("second");
+            assert_request_eq!(handle, "second").send_error(Error(true));
+            assert_err!(second.poll());
+
+            // Fresh requests still go straight through.
+            assert_ready!(svc.poll_ready());
+            let mut third = svc.call("third");
+            assert_request_eq!(handle, "third").send_response("fresh");
+            assert_eq!(third.wait().unwrap(), "fresh");
+
+            clock.advance(Duration::from_secs(2));
+            assert_not_ready!(first.poll());
+
+            assert_request_eq!(handle, "first").send_response("world");
+            assert_eq!(first.wait().unwrap(), "world");
+            assert_eq!(0, budget.in_use());
+        });
+    }
+
     #[derive(Debug, Clone)]
     struct SvcRetryLogic;
 
@@ -199,6 +478,14 @@ mod tests {
         fn is_retriable_error(&self, error: &Self::Error) -> bool {
             error.0
         }
+
+        fn should_retry_response(&self, response: &Self::Response) -> bool {
+            *response == "busy"
+        }
+
+        fn retry_after(&self, _response: &Self::Response) -> Option<Duration> {
+            Some(Duration::from_secs(10))
+        }
     }
 
     #[derive(Debug)]
@@ -0,0 +1,416 @@
+//! Batches shared by several sinks writing to the same destination, so that
+//! their events are sent together in fewer, larger requests.
+//!
+//! The first sink to join a shared queue builds the pipeline that batches and
+//! sends the items of every member, so sinks must only share a queue when
+//! they'd build the same pipeline. Each member keeps its own acker, and the
+//! events of each member are acked in order once the requests holding them
+//! complete. Members leave the queue when they're dropped.
+
+use super::{batch::Batch, state::SinkStateHandle};
+use crate::buffers::Acker;
+use futures::{sync::mpsc, Future, Poll, Sink, StartSend, Stream};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use tower::Service;
+
+pub type Pipeline = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+/// The shared queues of one kind of sink, by name.
+pub struct SharedQueues<T> {
+    queues: Mutex<HashMap<String, Weak<SharedQueue<T>>>>,
+}
+
+impl<T> Default for SharedQueues<T> {
+    fn default() -> Self {
+        Self {
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Send + 'static> SharedQueues<T> {
+    /// Joins the named queue, building its pipeline with `build` if no sink
+    /// currently belongs to it. The pipeline is handed the members' ackers
+    /// and the queue's items, and must send the items with `AckMembers` to
+    /// ack them. `state` is the state the pipeline reports to, which every
+    /// member shares. The queue is dropped, flushing its pipeline, once all of
+    /// its members are.
+    pub fn join<F>(
+        &self,
+        name: &str,
+        acker: Acker,
+        state: SinkStateHandle,
+        build: F,
+    ) -> MemberSink<T>
+    where
+        F: FnOnce(Members, mpsc::Receiver<T>) -> Pipeline,
+    {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = match queues.get(name).and_then(Weak::upgrade) {
+            Some(queue) => queue,
+            None => {
+                let (tx, rx) = mpsc::channel(1000);
+                let members = Members::default();
+                let pipeline = build(members.clone(), rx);
+                let queue = Arc::new(SharedQueue {
+                    tx,
+                    members,
+                    state,
+                    pipeline: Mutex::new(Some(pipeline)),
+                });
+                queues.insert(name.to_string(), Arc::downgrade(&queue));
+                queue
+            }
+        };
+
+        let member = queue.members.add(acker);
+        MemberSink {
+            tx: queue.tx.clone(),
+            member,
+            queue,
+        }
+    }
+}
+
+struct SharedQueue<T> {
+    tx: mpsc::Sender<T>,
+    members: Members,
+    state: SinkStateHandle,
+    pipeline: Mutex<Option<Pipeline>>,
+}
+
+/// The ackers of the members of a shared queue.
+#[derive(Clone, Default)]
+pub struct Members(Arc<Mutex<MemberAckers>>);
+
+// Members are never renumbered, so the events still in flight for a member
+// that left are never acked to another one.
+#[derive(Default)]
+struct MemberAckers {
+    next: usize,
+    ackers: HashMap<usize, Acker>,
+}
+
+impl Members {
+    fn add(&self, acker: Acker) -> usize {
+        let mut members = self.0.lock().unwrap();
+        let member = members.next;
+        members.next += 1;
+        members.ackers.insert(member, acker);
+        member
+    }
+
+    /// Removes a member. Its events still in flight are sent but not acked.
+    fn remove(&self, member: usize) {
+        self.0.lock().unwrap().ackers.remove(&member);
+    }
+
+    fn sent(&self, events: &[MemberEvents]) {
+        let members = self.0.lock().unwrap();
+        for events in events {
+            if let Some(acker) = members.ackers.get(&events.member) {
+                acker.sent(events.count);
+            }
+        }
+    }
+
+    fn ack(&self, events: &[MemberEvents]) {
+        let members = self.0.lock().unwrap();
+        for events in events {
+            if let Some(acker) = members.ackers.get(&events.member) {
+                acker.ack(events.count);
+            }
+        }
+    }
+}
+
+/// A number of consecutive events of a member.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemberEvents {
+    pub member: usize,
+    pub count: usize,
+}
+
+/// A sink sending its items to a shared queue, along with the identity of
+/// its member.
+pub struct MemberSink<T> {
+    queue: Arc<SharedQueue<T>>,
+    tx: mpsc::Sender<T>,
+    member: usize,
+}
+
+impl<T> MemberSink<T> {
+    pub fn member(&self) -> usize {
+        self.member
+    }
+
+    /// The state of the queue's pipeline.
+    pub fn state(&self) -> &SinkStateHandle {
+        &self.queue.state
+    }
+}
+
+impl<T> Drop for MemberSink<T> {
+    fn drop(&mut self) {
+        self.queue.members.remove(self.member);
+    }
+}
+
+impl<T: Send + 'static> Sink for MemberSink<T> {
+    type SinkItem = T;
+    type SinkError = ();
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        // The pipeline is only started once we're running on the runtime.
+        if let Some(pipeline) = self.queue.pipeline.lock().unwrap().take() {
+            tokio::spawn(pipeline);
+        }
+        self.tx.start_send(item).map_err(|error| {
+            error!(message = "shared queue is gone.", %error);
+        })
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.tx.poll_complete().map_err(|error| {
+            error!(message = "shared queue is gone.", %error);
+        })
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.poll_complete()
+    }
+}
+
+/// A batch recording which members its items came from and how many of
+/// their events each item stands for.
+#[derive(Debug)]
+pub struct MemberBuffer<T> {
+    inner: T,
+    events: Vec<MemberEvents>,
+}
+
+impl<T> MemberBuffer<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl<T: Batch> Batch for MemberBuffer<T> {
+    type Input = (T::Input, MemberEvents);
+    type Output = (T::Output, Vec<MemberEvents>);
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn push(&mut self, (item, events): Self::Input) {
+        match self.events.last_mut() {
+            Some(last) if last.member == events.member => last.count += events.count,
+            _ => self.events.push(events),
+        }
+        self.inner.push(item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        Self::new(self.inner.fresh())
+    }
+
+    fn finish(self) -> Self::Output {
+        (self.inner.finish(), self.events)
+    }
+
+    fn num_items(&self) -> usize {
+        self.events.iter().map(|events| events.count).sum()
+    }
+
+    fn should_flush(&self) -> bool {
+        self.inner.should_flush()
+    }
+}
+
+/// Acks the events of the members of a shared queue once the requests
+/// holding them complete, in the order the requests were made, like
+/// `BatchServiceSink` does for a single acker.
+pub struct AckMembers<S> {
+    inner: S,
+    members: Members,
+    acks: Arc<Mutex<PendingAcks>>,
+}
+
+#[derive(Default)]
+struct PendingAcks {
+    head: usize,
+    tail: usize,
+    done: HashMap<usize, Vec<MemberEvents>>,
+}
+
+impl<S> AckMembers<S> {
+    pub fn new(inner: S, members: Members) -> Self {
+        Self {
+            inner,
+            members,
+            acks: Arc::default(),
+        }
+    }
+}
+
+impl<S, R> Service<(R, Vec<MemberEvents>)> for AckMembers<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, (request, events): (R, Vec<MemberEvents>)) -> Self::Future {
+        let seqno = {
+            let mut acks = self.acks.lock().unwrap();
+            acks.head += 1;
+            acks.head - 1
+        };
+        self.members.sent(&events);
+
+        let members = self.members.clone();
+        let acks = Arc::clone(&self.acks);
+        Box::new(self.inner.call(request).inspect(move |_| {
+            let mut acks = acks.lock().unwrap();
+            acks.done.insert(seqno, events);
+            loop {
+                let tail = acks.tail;
+                match acks.done.remove(&tail) {
+                    Some(events) => {
+                        members.ack(&events);
+                        acks.tail += 1;
+                    }
+                    None => break,
+                }
+            }
+        }))
+    }
+}
+
+/// Runs a shared queue's items through a batched sink.
+pub fn forward<T, S>(rx: mpsc::Receiver<T>, sink: S) -> Pipeline
+where
+    T: Send + 'static,
+    S: Sink<SinkItem = T, SinkError = ()> + Send + 'static,
+{
+    Box::new(rx.forward(sink).map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::util::{BatchServiceSink, SinkExt};
+    use crate::test_util::wait_for;
+    use futures::{future, stream};
+    use std::sync::atomic::Ordering;
+
+    struct RecordRequests(Arc<Mutex<Vec<Vec<&'static str>>>>);
+
+    impl Service<Vec<&'static str>> for RecordRequests {
+        type Response = ();
+        type Error = std::io::Error;
+        type Future = future::FutureResult<(), std::io::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, batch: Vec<&'static str>) -> Self::Future {
+            self.0.lock().unwrap().push(batch);
+            future::ok(())
+        }
+    }
+
+    #[test]
+    fn members_share_batches_and_are_acked_separately() {
+        let queues = SharedQueues::<(&'static str, MemberEvents)>::default();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let (first_acker, first_acks) = Acker::new_for_testing();
+        let (second_acker, second_acks) = Acker::new_for_testing();
+        let recorded = Arc::clone(&requests);
+        let first = queues.join(
+            "cluster",
+            first_acker,
+            Default::default(),
+            move |members, rx| {
+                let service = AckMembers::new(RecordRequests(recorded), members);
+                let sink = BatchServiceSink::new(service, Acker::Null)
+                    .batched(MemberBuffer::new(Vec::new()), 5);
+                forward(rx, sink)
+            },
+        );
+        let second = queues.join("cluster", second_acker, Default::default(), |_, _| {
+            panic!("the queue already has a pipeline")
+        });
+        assert_eq!((0, 1), (first.member(), second.member()));
+
+        let events = |member, count| MemberEvents { member, count };
+        let mut rt = crate::test_util::runtime();
+        rt.block_on(
+            first
+                .send_all(stream::iter_ok(vec![
+                    ("a", events(0, 1)),
+                    ("b", events(0, 2)),
+                ]))
+                .and_then(|(first, _)| {
+                    second
+                        .send_all(stream::iter_ok(vec![
+                            ("c", events(1, 1)),
+                            ("d", events(1, 1)),
+                            ("e", events(1, 1)),
+                        ]))
+                        .map(move |(second, _)| (first, second))
+                }),
+        )
+        .unwrap();
+
+        wait_for(|| requests.lock().unwrap().len() == 1);
+        assert_eq!(
+            vec![vec!["a", "b", "c", "d", "e"]],
+            *requests.lock().unwrap()
+        );
+        wait_for(|| second_acks.load(Ordering::Relaxed) == 3);
+        assert_eq!(3, first_acks.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn members_leave_the_queue_when_dropped() {
+        let queues = SharedQueues::<()>::default();
+        let state = SinkStateHandle::default();
+        let join = |state| {
+            queues.join("cluster", Acker::Null, state, |_, rx| {
+                Box::new(rx.for_each(|_| Ok(())))
+            })
+        };
+
+        let first = join(state.clone());
+        let second = join(Default::default());
+        let members = first.queue.members.clone();
+        assert_eq!(2, members.0.lock().unwrap().ackers.len());
+        state.update(|state| state.in_flight_requests = 1);
+        assert_eq!(1, second.state().get().in_flight_requests);
+
+        drop(first);
+        assert_eq!(1, members.0.lock().unwrap().ackers.len());
+        let third = join(Default::default());
+        assert_eq!(2, third.member());
+        drop((second, third));
+        assert_eq!(0, members.0.lock().unwrap().ackers.len());
+    }
+}
@@ -0,0 +1,302 @@
+//! A registry of the internal state sinks publish about themselves, which the
+//! metrics server exposes at `/sinks` for operational dashboards.
+
+use super::{batch::Batch, retries::RetryBudget, Error};
+use bytes::Bytes;
+use futures::{AsyncSink, Future, Poll, Sink, StartSend};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tower::Service;
+
+lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<String, SinkStateHandle>> = Mutex::new(BTreeMap::new());
+}
+
+thread_local! {
+    static BUILDING: RefCell<Option<Option<SinkStateHandle>>> = RefCell::new(None);
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SinkState {
+    /// Events encoded into batches that haven't been sent yet
+    pub buffered_events: usize,
+    pub in_flight_requests: usize,
+    /// The most requests allowed in flight at once
+    pub concurrency: usize,
+    pub last_error: Option<String>,
+    /// Requests waiting to be retried or being retried
+    pub retrying_requests: usize,
+    pub circuit_breaker: CircuitBreaker,
+}
+
+/// Whether failed requests are retried. The breaker opens while the sink's
+/// retry budget is used up, and further failures are given up on right away
+/// until a retrying request completes.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitBreaker {
+    Closed,
+    Open,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::Closed
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SinkStateHandle {
+    state: Arc<Mutex<SinkState>>,
+    retry_budget: Arc<Mutex<Option<RetryBudget>>>,
+}
+
+impl SinkStateHandle {
+    pub fn update<F: FnOnce(&mut SinkState)>(&self, f: F) {
+        f(&mut self.state.lock().unwrap())
+    }
+
+    /// Reports the retries of `budget` and the circuit breaker it makes up.
+    pub fn track_retry_budget(&self, budget: RetryBudget) {
+        *self.retry_budget.lock().unwrap() = Some(budget);
+    }
+
+    pub fn get(&self) -> SinkState {
+        let mut state = self.state.lock().unwrap().clone();
+        if let Some(budget) = &*self.retry_budget.lock().unwrap() {
+            state.retrying_requests = budget.in_use();
+            state.circuit_breaker = if budget.is_exhausted() {
+                CircuitBreaker::Open
+            } else {
+                CircuitBreaker::Closed
+            };
+        }
+        state
+    }
+}
+
+/// Runs `build`, returning what it built along with the state the sink it
+/// built published, if any.
+pub fn building_sink<T, F: FnOnce() -> T>(build: F) -> (T, Option<SinkStateHandle>) {
+    BUILDING.with(|building| *building.borrow_mut() = Some(None));
+    let built = build();
+    let published = BUILDING.with(|building| building.borrow_mut().take().and_then(|state| state));
+    (built, published)
+}
+
+/// Creates the state of the sink being built, which the topology registers
+/// once the sink is running. Sinks built outside of `building_sink`, as in
+/// tests, get a handle that is never registered.
+pub fn publish() -> SinkStateHandle {
+    let handle = SinkStateHandle::default();
+    BUILDING.with(|building| {
+        if let Some(published) = &mut *building.borrow_mut() {
+            *published = Some(handle.clone());
+        }
+    });
+    handle
+}
+
+/// Makes `handle` the state of the sink being built in place of the one it
+/// published, for sinks reporting the state of something they share.
+pub fn republish(handle: SinkStateHandle) {
+    BUILDING.with(|building| {
+        if let Some(published) = &mut *building.borrow_mut() {
+            *published = Some(handle);
+        }
+    });
+}
+
+/// Registers the state of a running sink under its name, replacing the state
+/// of any sink previously registered under it.
+pub fn register(name: &str, state: SinkStateHandle) {
+    REGISTRY.lock().unwrap().insert(name.to_string(), state);
+}
+
+pub fn unpublish(name: &str) {
+    REGISTRY.lock().unwrap().remove(name);
+}
+
+/// The state of every published sink, by name.
+pub fn snapshot() -> BTreeMap<String, SinkState> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, handle)| (name.clone(), handle.get()))
+        .collect()
+}
+
+/// Counts the events of batches as sent once the inner sink accepts them.
+pub struct TrackBuffered<S> {
+    inner: S,
+    state: SinkStateHandle,
+}
+
+impl<S> TrackBuffered<S> {
+    pub fn new(inner: S, state: SinkStateHandle) -> Self {
+        Self { inner, state }
+    }
+}
+
+impl<S> Sink for TrackBuffered<S>
+where
+    S: Sink,
+    S::SinkItem: Batch,
+{
+    type SinkItem = S::SinkItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, batch: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let items = batch.num_items();
+        let result = self.inner.start_send(batch)?;
+        if let AsyncSink::Ready = result {
+            self.state.update(|state| {
+                state.buffered_events = state.buffered_events.saturating_sub(items)
+            });
+        }
+        Ok(result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.close()
+    }
+}
+
+/// Tracks the requests in flight through an HTTP service and the last one to
+/// fail.
+#[derive(Clone)]
+pub struct TrackRequests<S> {
+    inner: S,
+    state: SinkStateHandle,
+}
+
+impl<S> TrackRequests<S> {
+    pub fn new(inner: S, state: SinkStateHandle) -> Self {
+        Self { inner, state }
+    }
+}
+
+impl<S, T> Service<T> for TrackRequests<S>
+where
+    S: Service<T, Response = http::Response<Bytes>>,
+    S::Error: Into<Error>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: T) -> Self::Future {
+        self.state.update(|state| state.in_flight_requests += 1);
+
+        let state = self.state.clone();
+        Box::new(self.inner.call(request).map_err(Into::into).then(
+            move |result: Result<S::Response, Error>| {
+                let error = match &result {
+                    Ok(response) if response.status().is_success() => None,
+                    Ok(response) => Some(format!("Unexpected status: {}", response.status())),
+                    Err(error) => Some(error.to_string()),
+                };
+                state.update(|state| {
+                    state.in_flight_requests -= 1;
+                    if error.is_some() {
+                        state.last_error = error;
+                    }
+                });
+                result
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::util::Buffer;
+
+    struct StatusService(u16);
+
+    impl Service<()> for StatusService {
+        type Response = http::Response<Bytes>;
+        type Error = Error;
+        type Future = futures::future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            let response = http::Response::builder()
+                .status(self.0)
+                .body(Bytes::new())
+                .unwrap();
+            futures::future::ok(response)
+        }
+    }
+
+    #[test]
+    fn publishes_sink_state() {
+        let (state, published) = building_sink(publish);
+        assert!(published.is_some());
+        register("state_test_sink", state.clone());
+        state.update(|state| {
+            state.concurrency = 5;
+            state.buffered_events = 3;
+        });
+
+        let mut sink = TrackBuffered::new(Vec::new(), state.clone());
+        let mut batch = Buffer::new(false);
+        batch.push(b"one");
+        batch.push(b"two");
+        sink.start_send(batch).unwrap();
+
+        let mut ok = TrackRequests::new(StatusService(200), state.clone());
+        let mut failing = TrackRequests::new(StatusService(500), state.clone());
+        let request = failing.call(());
+        ok.call(()).wait().unwrap();
+        assert_eq!(1, state.get().in_flight_requests);
+        request.wait().unwrap();
+
+        let expected = SinkState {
+            buffered_events: 1,
+            in_flight_requests: 0,
+            concurrency: 5,
+            last_error: Some("Unexpected status: 500 Internal Server Error".into()),
+            retrying_requests: 0,
+            circuit_breaker: CircuitBreaker::Closed,
+        };
+        assert_eq!(Some(&expected), snapshot().get("state_test_sink"));
+
+        unpublish("state_test_sink");
+        assert_eq!(None, snapshot().get("state_test_sink"));
+    }
+
+    #[test]
+    fn reports_circuit_breaker_from_retry_budget() {
+        let state = publish();
+        assert_eq!(CircuitBreaker::Closed, state.get().circuit_breaker);
+
+        state.track_retry_budget(RetryBudget::new(0));
+        assert_eq!(CircuitBreaker::Open, state.get().circuit_breaker);
+
+        state.track_retry_budget(RetryBudget::new(1));
+        let expected = SinkState {
+            retrying_requests: 0,
+            circuit_breaker: CircuitBreaker::Closed,
+            ..SinkState::default()
+        };
+        assert_eq!(expected, state.get());
+    }
+}
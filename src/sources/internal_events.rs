@@ -0,0 +1,142 @@
+//! Events vector's own components emit about themselves, e.g. requests a sink
+//! permanently failed to send or counters of the events it encoded, so they
+//! can be routed through the topology like any other events.
+
+use crate::{
+    event::Event,
+    topology::config::{DataType, GlobalOptions, SourceConfig},
+};
+use futures::{sync::mpsc, Future, Sink, Stream};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<mpsc::Sender<Event>>> = Mutex::new(Vec::new());
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct InternalEventsConfig {
+    pub include_metrics: Option<bool>,
+}
+
+#[typetag::serde(name = "internal_events")]
+impl SourceConfig for InternalEventsConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> Result<super::Source, String> {
+        Ok(internal_events(
+            subscribe(),
+            out,
+            self.include_metrics.unwrap_or(false),
+        ))
+    }
+
+    fn output_type(&self) -> DataType {
+        if self.include_metrics.unwrap_or(false) {
+            DataType::Any
+        } else {
+            DataType::Log
+        }
+    }
+}
+
+/// Receives every internal event emitted from now on.
+pub fn subscribe() -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel(1000);
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    rx
+}
+
+/// Hands an event to every subscriber. Subscribers that fall behind miss
+/// events rather than holding up the component emitting them.
+pub fn emit(event: Event) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|tx| !tx.is_closed());
+    for tx in subscribers.iter_mut() {
+        if tx.try_send(event.clone()).is_err() {
+            debug!(
+                message = "internal events subscriber is full; dropping event.",
+                dropped_internal_events_counter = 1
+            );
+        }
+    }
+}
+
+/// Forwards internal events to `out`, leaving out metrics unless they're
+/// included.
+pub fn internal_events(
+    rx: mpsc::Receiver<Event>,
+    out: mpsc::Sender<Event>,
+    include_metrics: bool,
+) -> super::Source {
+    Box::new(
+        rx.filter(move |event| match event {
+            Event::Log(_) => true,
+            Event::Metric(_) => include_metrics,
+        })
+        .forward(out.sink_map_err(|e| error!("error sending event: {:?}", e)))
+        .map(|_| info!("finished sending internal events")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{self, Metric};
+    use crate::test_util::runtime;
+
+    #[test]
+    fn forwards_emitted_events() {
+        let (tx, rx) = mpsc::channel(10);
+        let source = InternalEventsConfig::default()
+            .build("internal", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        emit(Event::from("first"));
+        emit(Event::from("second"));
+
+        let mut rt = runtime();
+        rt.spawn(source);
+        // Other tests may emit events of their own meanwhile.
+        let messages = rx
+            .map(|event| event.as_log()[&event::MESSAGE].to_string_lossy())
+            .filter(|message| message == "first" || message == "second")
+            .take(2)
+            .collect();
+        assert_eq!(vec!["first", "second"], rt.block_on(messages).unwrap());
+    }
+
+    #[test]
+    fn forwards_metrics_when_included() {
+        let (tx, rx) = mpsc::channel(10);
+        let config = InternalEventsConfig {
+            include_metrics: Some(true),
+        };
+        assert_eq!(DataType::Any, config.output_type());
+        let source = config
+            .build("internal", &GlobalOptions::default(), tx)
+            .unwrap();
+
+        emit(Event::Metric(Metric::Counter {
+            name: "forwarded_metric_total".into(),
+            val: 1.0,
+            timestamp: None,
+            tags: None,
+        }));
+
+        let mut rt = runtime();
+        rt.spawn(source);
+        let metric = rx
+            .filter(|event| match event {
+                Event::Metric(Metric::Counter { name, .. }) => name == "forwarded_metric_total",
+                _ => false,
+            })
+            .into_future();
+        assert!(rt.block_on(metric).map_err(|_| ()).unwrap().0.is_some());
+    }
+}
@@ -3,7 +3,7 @@ use crate::{
     Event,
 };
 use bytes::Bytes;
-use chrono::{format::strftime::StrftimeItems, Utc};
+use chrono::{format::strftime::StrftimeItems, FixedOffset, Utc};
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
 use serde::{
@@ -23,6 +23,8 @@ pub struct Template {
     src_bytes: Bytes,
     has_ts: bool,
     has_fields: bool,
+    // Timestamps are formatted in UTC unless an offset is given.
+    timezone: Option<FixedOffset>,
 }
 
 impl From<&str> for Template {
@@ -32,6 +34,7 @@ impl From<&str> for Template {
             src_bytes: src.into(),
             has_ts: StrftimeItems::new(src).count() > 0,
             has_fields: RE.is_match(src),
+            timezone: None,
         }
     }
 }
@@ -47,10 +50,10 @@ impl Template {
         match (self.has_fields, self.has_ts) {
             (false, false) => Ok(self.src_bytes.clone()),
             (true, false) => render_fields(&self.src, event).map(Bytes::from),
-            (false, true) => Ok(render_timestamp(&self.src, event).into()),
+            (false, true) => Ok(render_timestamp(&self.src, event, self.timezone).into()),
             (true, true) => {
                 let tmp = render_fields(&self.src, event)?;
-                Ok(render_timestamp(&tmp, event).into())
+                Ok(render_timestamp(&tmp, event, self.timezone).into())
             }
         }
     }
@@ -67,21 +70,76 @@ impl Template {
     pub fn get_ref(&self) -> &Bytes {
         &self.src_bytes
     }
+
+    /// Format timestamps in the given zone rather than UTC.
+    pub fn with_timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+}
+
+/// Parses a timezone given as `UTC`, `Z` or a fixed `+HH:MM`/`-HH:MM` offset.
+pub fn parse_timezone(tz: &str) -> Result<FixedOffset, String> {
+    let invalid = || {
+        format!(
+            "invalid timezone {:?}, expected \"UTC\" or an offset like \"+09:00\"",
+            tz
+        )
+    };
+    let tz = tz.trim();
+    if tz.eq_ignore_ascii_case("utc") || tz == "Z" {
+        return Ok(FixedOffset::east(0));
+    }
+
+    let sign = match tz.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    // Signs are only allowed up front, so both parts are plain digits.
+    let two_digits = |part: &str| {
+        if part.len() == 2 && part.bytes().all(|b| b.is_ascii_digit()) {
+            part.parse::<u32>().ok()
+        } else {
+            None
+        }
+    };
+    let mut parts = tz[1..].splitn(2, ':');
+    let hours = parts.next().and_then(two_digits);
+    let minutes = parts.next().and_then(two_digits);
+    match (hours, minutes) {
+        (Some(hours), Some(minutes)) if hours < 24 && minutes < 60 => {
+            let seconds = (hours * 3600 + minutes * 60) as i32;
+            Ok(FixedOffset::east(sign * seconds))
+        }
+        _ => Err(invalid()),
+    }
 }
 
+// A reference may give a default for when the field is missing, as in
+// `{{ service:-unknown }}`. Nested data is stored under flattened dotted keys,
+// so `{{ kubernetes.namespace }}` resolves a path the same way `unflatten`
+// nests it.
 fn render_fields(src: &str, event: &Event) -> Result<String, Vec<Atom>> {
     let mut missing_fields = Vec::new();
     let out = RE
         .replace_all(src, |caps: &Captures<'_>| {
-            let key = caps
+            let reference = caps
                 .get(1)
-                .map(|s| Atom::from(s.as_str().trim()))
+                .map(|s| s.as_str().trim())
                 .expect("src should match regex");
-            if let Some(val) = event.as_log().get(&key) {
-                val.to_string_lossy()
-            } else {
-                missing_fields.push(key.clone());
-                String::new()
+            let (key, default) = match reference.find(":-") {
+                Some(at) => (&reference[..at], Some(&reference[at + 2..])),
+                None => (reference, None),
+            };
+            let key = Atom::from(key.trim());
+            match (event.as_log().get(&key), default) {
+                (Some(val), _) => val.to_string_lossy(),
+                (None, Some(default)) => default.to_string(),
+                (None, None) => {
+                    missing_fields.push(key.clone());
+                    String::new()
+                }
             }
         })
         .into_owned();
@@ -92,15 +150,15 @@ fn render_fields(src: &str, event: &Event) -> Result<String, Vec<Atom>> {
     }
 }
 
-fn render_timestamp(src: &str, event: &Event) -> String {
+fn render_timestamp(src: &str, event: &Event, timezone: Option<FixedOffset>) -> String {
     let timestamp = match event {
         Event::Log(log) => log.get(&event::TIMESTAMP).and_then(ValueKind::as_timestamp),
         _ => None,
     };
-    if let Some(ts) = timestamp {
-        ts.format(src).to_string()
-    } else {
-        Utc::now().format(src).to_string()
+    let ts = timestamp.cloned().unwrap_or_else(Utc::now);
+    match timezone {
+        Some(timezone) => ts.with_timezone(&timezone).format(src).to_string(),
+        None => ts.format(src).to_string(),
     }
 }
 
@@ -144,6 +202,10 @@ impl Serialize for Template {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transforms::{
+        json_parser::{JsonParser, JsonParserConfig},
+        Transform,
+    };
     use chrono::TimeZone;
 
     #[test]
@@ -198,6 +260,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn render_dynamic_with_defaults() {
+        let template = Template::from("{{ service:-unknown }}-{{ env :- prod }}-{{ region }}");
+
+        let mut event = Event::from("hello world");
+        event
+            .as_mut_log()
+            .insert_implicit("region".into(), "eu".into());
+        assert_eq!(Ok(Bytes::from("unknown-prod-eu")), template.render(&event));
+
+        event
+            .as_mut_log()
+            .insert_implicit("service".into(), "api".into());
+        assert_eq!(Ok(Bytes::from("api-prod-eu")), template.render(&event));
+
+        let event = Event::from("hello world");
+        assert_eq!(Err(vec![Atom::from("region")]), template.render(&event));
+    }
+
     #[test]
     fn render_dynamic_multiple_keys() {
         let mut event = Event::from("hello world");
@@ -232,6 +313,27 @@ mod tests {
         )
     }
 
+    #[test]
+    fn render_dynamic_with_nested_fields() {
+        // Nested objects are flattened into dotted keys as they're parsed.
+        let mut parser = JsonParser::from(JsonParserConfig::default());
+        let event = parser
+            .transform(Event::from(
+                r#"{"kubernetes": {"namespace": "default", "labels": {"app": "api"}}}"#,
+            ))
+            .unwrap();
+
+        let template =
+            Template::from("logs-{{ kubernetes.namespace }}-{{ kubernetes.labels.app }}");
+        assert_eq!(Ok(Bytes::from("logs-default-api")), template.render(&event));
+
+        let template = Template::from("logs-{{ kubernetes.pod.name }}");
+        assert_eq!(
+            Err(vec![Atom::from("kubernetes.pod.name")]),
+            template.render(&event)
+        );
+    }
+
     #[test]
     fn render_timestamp_strftime_style() {
         let ts = Utc.ymd(2001, 2, 3).and_hms(4, 5, 6);
@@ -263,6 +365,40 @@ mod tests {
         )
     }
 
+    #[test]
+    fn render_timestamp_in_timezone() {
+        let ts = Utc.ymd(2001, 2, 3).and_hms(20, 5, 6);
+
+        let mut event = Event::from("hello world");
+        event
+            .as_mut_log()
+            .insert_implicit(crate::event::TIMESTAMP.clone(), ts.into());
+
+        let utc = Template::from("vector-%Y.%m.%d");
+        let tokyo =
+            Template::from("vector-%Y.%m.%d").with_timezone(parse_timezone("+09:00").unwrap());
+
+        assert_eq!(Ok(Bytes::from("vector-2001.02.03")), utc.render(&event));
+        assert_eq!(Ok(Bytes::from("vector-2001.02.04")), tokyo.render(&event));
+    }
+
+    #[test]
+    fn parses_timezones() {
+        assert_eq!(Ok(FixedOffset::east(0)), parse_timezone("UTC"));
+        assert_eq!(Ok(FixedOffset::east(0)), parse_timezone("Z"));
+        assert_eq!(Ok(FixedOffset::east(9 * 3600)), parse_timezone("+09:00"));
+        assert_eq!(
+            Ok(FixedOffset::west(5 * 3600 + 1800)),
+            parse_timezone("-05:30")
+        );
+        assert!(parse_timezone("Asia/Tokyo").is_err());
+        assert!(parse_timezone("+25:00").is_err());
+        assert!(parse_timezone("+-05:00").is_err());
+        assert!(parse_timezone("+05:-30").is_err());
+        assert!(parse_timezone("+5:30").is_err());
+        assert!(parse_timezone("+09").is_err());
+    }
+
     #[test]
     fn render_dynamic_with_strftime() {
         let ts = Utc.ymd(2001, 2, 3).and_hms(4, 5, 6);
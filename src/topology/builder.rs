@@ -1,5 +1,5 @@
 use super::fanout::{self, Fanout};
-use crate::{buffers, topology::config::GlobalOptions};
+use crate::{buffers, sinks::util::state, topology::config::GlobalOptions};
 use futures::{
     future::{lazy, Either},
     sync::mpsc,
@@ -19,6 +19,7 @@ pub struct Pieces {
     pub source_tasks: HashMap<String, Task>,
     pub healthchecks: HashMap<String, Task>,
     pub shutdown_triggers: HashMap<String, Trigger>,
+    pub sink_states: HashMap<String, state::SinkStateHandle>,
 }
 
 pub fn build_pieces(config: &super::Config) -> Result<(Pieces, Vec<String>), Vec<String>> {
@@ -28,6 +29,7 @@ pub fn build_pieces(config: &super::Config) -> Result<(Pieces, Vec<String>), Vec
     let mut source_tasks = HashMap::new();
     let mut healthchecks = HashMap::new();
     let mut shutdown_triggers = HashMap::new();
+    let mut sink_states = HashMap::new();
 
     let mut errors = vec![];
     let mut warnings = vec![];
@@ -107,12 +109,17 @@ pub fn build_pieces(config: &super::Config) -> Result<(Pieces, Vec<String>), Vec
             Ok(buffer) => buffer,
         };
 
-        let (sink, healthcheck) = match sink.inner.build(acker) {
-            Err(error) => {
+        let (sink, healthcheck) = match state::building_sink(|| sink.inner.build(acker)) {
+            (Err(error), _) => {
                 errors.push(format!("Sink \"{}\": {}", name, error));
                 continue;
             }
-            Ok((sink, healthcheck)) => (sink, healthcheck),
+            (Ok((sink, healthcheck)), sink_state) => {
+                if let Some(sink_state) = sink_state {
+                    sink_states.insert(name.clone(), sink_state);
+                }
+                (sink, healthcheck)
+            }
         };
 
         let task = rx.forward(sink).map(|_| ());
@@ -204,6 +211,7 @@ pub fn build_pieces(config: &super::Config) -> Result<(Pieces, Vec<String>), Vec
             source_tasks,
             healthchecks,
             shutdown_triggers,
+            sink_states,
         };
 
         Ok((pieces, warnings))
@@ -17,6 +17,24 @@ pub struct Config {
     pub sinks: IndexMap<String, SinkOuter>,
     #[serde(default)]
     pub transforms: IndexMap<String, TransformOuter>,
+    #[serde(default)]
+    pub healthcheck_gate: Option<HealthcheckGate>,
+}
+
+/// Holds sources back until the healthchecks of all sinks pass, retrying
+/// failed healthchecks every `retry_interval_secs` and failing attempts that
+/// take longer than `attempt_timeout_secs`. If the sinks are still
+/// unhealthy after `timeout_secs` the topology is aborted rather than waiting
+/// forever on a sink that may never come up.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthcheckGate {
+    #[serde(default = "healthcheck_gate_timeout_default")]
+    pub timeout_secs: u64,
+    #[serde(default = "healthcheck_gate_retry_interval_default")]
+    pub retry_interval_secs: u64,
+    #[serde(default = "healthcheck_gate_attempt_timeout_default")]
+    pub attempt_timeout_secs: u64,
 }
 
 #[derive(Default)]
@@ -126,6 +144,13 @@ pub trait SinkConfig: core::fmt::Debug {
         acker: crate::buffers::Acker,
     ) -> Result<(sinks::RouterSink, sinks::Healthcheck), String>;
 
+    /// Makes the sink's healthcheck on its own, so that a failed one can be
+    /// retried without building the whole sink again. Sinks that only check
+    /// their health as part of `build` aren't retried.
+    fn healthcheck_factory(&self) -> Option<sinks::HealthcheckFactory> {
+        None
+    }
+
     fn input_type(&self) -> DataType;
 }
 
@@ -153,6 +178,7 @@ impl Config {
             sources: IndexMap::new(),
             sinks: IndexMap::new(),
             transforms: IndexMap::new(),
+            healthcheck_gate: None,
         }
     }
 
@@ -239,3 +265,15 @@ impl Clone for Config {
 fn healthcheck_default() -> bool {
     true
 }
+
+fn healthcheck_gate_timeout_default() -> u64 {
+    300
+}
+
+fn healthcheck_gate_retry_interval_default() -> u64 {
+    5
+}
+
+fn healthcheck_gate_attempt_timeout_default() -> u64 {
+    10
+}
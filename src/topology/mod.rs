@@ -4,11 +4,12 @@ mod fanout;
 
 pub use self::config::Config;
 
-use crate::topology::builder::Pieces;
+use crate::topology::{builder::Pieces, config::SinkOuter};
 
 use crate::buffers;
+use crate::sinks::{self, util::state};
 use futures::{
-    future,
+    future::{self, Either, Loop, Shared},
     sync::{mpsc, oneshot},
     Future, Stream,
 };
@@ -17,7 +18,7 @@ use std::collections::{HashMap, HashSet};
 use std::panic::AssertUnwindSafe;
 use std::time::{Duration, Instant};
 use stream_cancel::Trigger;
-use tokio::timer;
+use tokio::{timer, util::FutureExt};
 use tracing_futures::Instrument;
 
 #[allow(dead_code)]
@@ -29,6 +30,7 @@ pub struct RunningTopology {
     shutdown_triggers: HashMap<String, Trigger>,
     config: Config,
     abort_tx: mpsc::UnboundedSender<()>,
+    source_gate: Option<Shared<builder::Task>>,
 }
 
 pub fn start(
@@ -55,6 +57,7 @@ pub fn start_validated(
         source_tasks: HashMap::new(),
         tasks: HashMap::new(),
         abort_tx,
+        source_gate: None,
     };
 
     if !running_topology.run_healthchecks(&config, &mut pieces, rt, require_healthy) {
@@ -189,7 +192,50 @@ impl RunningTopology {
         let (_, sinks_to_change, sinks_to_add) =
             to_remove_change_add(&self.config.sinks, &new_config.sinks);
 
-        let healthchecks = (&sinks_to_change | &sinks_to_add)
+        let names = &sinks_to_change | &sinks_to_add;
+        self.source_gate = None;
+
+        if let (Some(gate), false) = (&new_config.healthcheck_gate, require_healthy) {
+            let retry_interval = Duration::from_secs(gate.retry_interval_secs);
+            let attempt_timeout = Duration::from_secs(gate.attempt_timeout_secs);
+            let healthchecks = names
+                .into_iter()
+                .map(|name| {
+                    let first = pieces.healthchecks.remove(&name).unwrap();
+                    retry_healthcheck(
+                        name.clone(),
+                        first,
+                        &new_config.sinks[&name],
+                        retry_interval,
+                        attempt_timeout,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let timeout_secs = gate.timeout_secs;
+            let gate = future::join_all(healthchecks)
+                .map(|_| info!("All healthchecks passed; starting sources."))
+                .timeout(Duration::from_secs(timeout_secs))
+                .map_err(move |error| {
+                    if error.is_elapsed() {
+                        error!(
+                            "Sinks still unhealthy after {} seconds; not starting sources.",
+                            timeout_secs
+                        )
+                    } else {
+                        error!("Sinks unhealthy; not starting sources.")
+                    }
+                });
+            let gate: builder::Task = Box::new(gate);
+            let gate = gate.shared();
+
+            info!("Running healthchecks; sources wait until they pass.");
+            rt.spawn(gate.clone().then(|_| Ok(())));
+            self.source_gate = Some(gate);
+            return true;
+        }
+
+        let healthchecks = names
             .into_iter()
             .map(|name| pieces.healthchecks.remove(&name).unwrap())
             .collect::<Vec<_>>();
@@ -293,6 +339,7 @@ impl RunningTopology {
             info!("Removing sink {:?}", name);
 
             self.tasks.remove(&name).unwrap().forget();
+            state::unpublish(&name);
 
             self.remove_inputs(&name);
         }
@@ -327,6 +374,11 @@ impl RunningTopology {
         if let Some(previous) = self.tasks.insert(name.to_string(), spawned) {
             previous.forget();
         }
+
+        match new_pieces.sink_states.remove(name) {
+            Some(sink_state) => state::register(name, sink_state),
+            None => state::unpublish(name),
+        }
     }
 
     fn spawn_transform(
@@ -363,6 +415,13 @@ impl RunningTopology {
             .insert(name.clone(), shutdown_trigger);
 
         let source_task = new_pieces.source_tasks.remove(name).unwrap();
+        let source_task: builder::Task = match &self.source_gate {
+            Some(gate) => Box::new(gate.clone().then(move |result| match result {
+                Ok(_) => Either::A(source_task),
+                Err(_) => Either::B(future::err(())),
+            })),
+            None => source_task,
+        };
         let source_task = handle_errors(source_task, self.abort_tx.clone());
         let source_task = source_task.instrument(info_span!("source", name = name.as_str()));
         self.source_tasks
@@ -507,6 +566,53 @@ where
     (to_remove, to_change, to_add)
 }
 
+// Retries a sink's healthcheck until it passes. A healthcheck future can only
+// run once, so later attempts are made by the sink's healthcheck factory; the
+// first failure is final for sinks without one. A failing timer fails the
+// attempt like an unhealthy sink would.
+fn retry_healthcheck(
+    name: String,
+    first: builder::Task,
+    sink: &SinkOuter,
+    retry_interval: Duration,
+    attempt_timeout: Duration,
+) -> builder::Task {
+    if !sink.healthcheck {
+        return first;
+    }
+    let factory = match sink.inner.healthcheck_factory() {
+        Some(factory) => factory,
+        None => return first,
+    };
+
+    let span = info_span!("healthcheck", name = name.as_str());
+    let retries = future::loop_fn(factory, move |factory| {
+        timer::Delay::new(Instant::now() + retry_interval).then(move |delay| {
+            let attempt: sinks::Healthcheck = match delay {
+                Ok(()) => Box::new(
+                    factory()
+                        .timeout(attempt_timeout)
+                        .map_err(|err| err.to_string()),
+                ),
+                Err(err) => Box::new(future::err(format!("Timer error: {}", err))),
+            };
+            attempt.then(move |result| match result {
+                Ok(()) => {
+                    info!("Healthcheck: Passed.");
+                    Ok::<_, ()>(Loop::Break(()))
+                }
+                Err(err) => {
+                    error!("Healthcheck: Failed Reason: {}", err);
+                    Ok(Loop::Continue(factory))
+                }
+            })
+        })
+    })
+    .instrument(span);
+
+    Box::new(first.or_else(|_| retries))
+}
+
 fn handle_errors(
     task: builder::Task,
     abort_tx: mpsc::UnboundedSender<()>,
@@ -1,4 +1,4 @@
-use futures::Future;
+use futures::{Future, Sink, Stream};
 use prost::Message;
 use tempfile::tempdir;
 use vector::event::{self, Event};
@@ -29,6 +29,7 @@ fn test_buffering() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size: 10_000,
         when_full: Default::default(),
+        max_age_secs: None,
     };
     config.data_dir = Some(data_dir.clone());
 
@@ -57,6 +58,7 @@ fn test_buffering() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size: 10_000,
         when_full: Default::default(),
+        max_age_secs: None,
     };
     config.data_dir = Some(data_dir);
 
@@ -120,6 +122,7 @@ fn test_max_size() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size,
         when_full: Default::default(),
+        max_age_secs: None,
     };
     config.data_dir = Some(data_dir.clone());
 
@@ -147,6 +150,7 @@ fn test_max_size() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size,
         when_full: Default::default(),
+        max_age_secs: None,
     };
     config.data_dir = Some(data_dir);
 
@@ -191,6 +195,7 @@ fn test_max_size_resume() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size,
         when_full: Default::default(),
+        max_age_secs: None,
     };
     config.data_dir = Some(data_dir.clone());
 
@@ -244,6 +249,7 @@ fn test_reclaim_disk_space() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size: 1_000_000_000,
         when_full: Default::default(),
+        max_age_secs: None,
     }
     .into();
     config.data_dir = Some(data_dir.clone());
@@ -281,6 +287,7 @@ fn test_reclaim_disk_space() {
     config.sinks["out"].buffer = BufferConfig::Disk {
         max_size: 1_000_000_000,
         when_full: Default::default(),
+        max_age_secs: None,
     };
     config.data_dir = Some(data_dir.clone());
 
@@ -318,3 +325,45 @@ fn test_reclaim_disk_space() {
     println!("after {}, before {}", after_disk_size, before_disk_size);
     assert!(after_disk_size < before_disk_size);
 }
+
+#[test]
+fn test_buffering_drops_stale_events() {
+    let data_dir = tempdir().unwrap();
+    let data_dir = Some(data_dir.path().to_path_buf());
+
+    let mut stale = Event::from("stale");
+    stale.as_mut_log().insert_explicit(
+        event::TIMESTAMP.clone(),
+        (chrono::Utc::now() - chrono::Duration::hours(2)).into(),
+    );
+
+    let buffer = BufferConfig::Disk {
+        max_size: 10_000,
+        when_full: Default::default(),
+        max_age_secs: Some(3600),
+    };
+    let (tx, rx, acker) = buffer.build(&data_dir, "stale").unwrap();
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let events = vec![stale, Event::from("fresh")];
+    rt.block_on(tx.get().send_all(futures::stream::iter_ok::<_, ()>(events)))
+        .unwrap();
+    drop(tx);
+
+    let (output, rx) = rt.block_on(rx.into_future()).ok().unwrap();
+    assert_eq!(
+        "fresh",
+        output.unwrap().as_log()[&event::MESSAGE].to_string_lossy()
+    );
+
+    // Acking the fresh event deletes the stale one along with it.
+    acker.ack(1);
+    drop(rx);
+    let buffer = BufferConfig::Disk {
+        max_size: 10_000,
+        when_full: Default::default(),
+        max_age_secs: None,
+    };
+    let (tx, rx, _acker) = buffer.build(&data_dir, "stale").unwrap();
+    drop(tx);
+    assert!(rt.block_on(rx.collect()).unwrap().is_empty());
+}
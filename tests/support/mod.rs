@@ -8,7 +8,10 @@ use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use vector::buffers::Acker;
 use vector::event::{Event, Metric, ValueKind, MESSAGE};
-use vector::sinks::{util::SinkExt, Healthcheck, RouterSink};
+use vector::sinks::{
+    util::{state, SinkExt},
+    Healthcheck, HealthcheckFactory, RouterSink,
+};
 use vector::sources::Source;
 use vector::topology::config::{
     DataType, GlobalOptions, SinkConfig, SourceConfig, TransformConfig,
@@ -179,6 +182,7 @@ impl MockSinkConfig {
 #[typetag::serde(name = "mock")]
 impl SinkConfig for MockSinkConfig {
     fn build(&self, acker: Acker) -> Result<(RouterSink, Healthcheck), String> {
+        state::publish();
         let sink = self
             .sender
             .clone()
@@ -192,6 +196,16 @@ impl SinkConfig for MockSinkConfig {
         Ok((Box::new(sink), Box::new(healthcheck)))
     }
 
+    fn healthcheck_factory(&self) -> Option<HealthcheckFactory> {
+        let healthy = self.healthy;
+        Some(Box::new(move || -> Healthcheck {
+            match healthy {
+                true => Box::new(future::ok(())),
+                false => Box::new(future::err("unhealthy".to_owned())),
+            }
+        }))
+    }
+
     fn input_type(&self) -> DataType {
         DataType::Any
     }
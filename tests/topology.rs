@@ -11,9 +11,10 @@ use std::sync::{
     Arc,
 };
 use vector::event::{Event, MESSAGE};
+use vector::sinks::util::state;
 use vector::test_util::{runtime, shutdown_on_idle, trace_init};
 use vector::topology;
-use vector::topology::config::Config;
+use vector::topology::config::{Config, HealthcheckGate};
 
 fn basic_config() -> Config {
     let mut config = Config::empty();
@@ -229,6 +230,32 @@ fn topology_remove_one_sink() {
     assert_eq!(Vec::<Event>::new(), res2);
 }
 
+#[test]
+fn topology_remove_sink_unpublishes_its_state() {
+    let mut rt = runtime();
+
+    let mut config = Config::empty();
+    config.add_source("in1", source().1);
+    config.add_sink("published_out1", &["in1"], sink().1);
+    config.add_sink("published_out2", &["in1"], sink().1);
+
+    let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+    assert!(state::snapshot().contains_key("published_out2"));
+
+    let mut config = Config::empty();
+    config.add_source("in1", source().1);
+    config.add_sink("published_out1", &["in1"], sink().1);
+
+    assert!(topology.reload_config_and_respawn(config, &mut rt, false));
+
+    let published = state::snapshot();
+    assert!(published.contains_key("published_out1"));
+    assert!(!published.contains_key("published_out2"));
+
+    rt.block_on(topology.stop()).unwrap();
+    shutdown_on_idle(rt);
+}
+
 #[test]
 fn topology_remove_one_transform() {
     let mut rt = runtime();
@@ -475,3 +502,63 @@ fn topology_healthcheck_run_for_changes_on_reload() {
     config.add_sink("out2", &["in1"], sink_failing_healthcheck().1);
     assert!(topology.reload_config_and_respawn(config, &mut rt, true) == false);
 }
+
+#[test]
+fn topology_healthcheck_gate_starts_sources_once_healthy() {
+    let mut rt = runtime();
+    let (in1, source1) = source();
+    let (out1, sink1) = sink();
+
+    let mut config = Config::empty();
+    config.add_source("in1", source1);
+    config.add_sink("out1", &["in1"], sink1);
+    config.healthcheck_gate = Some(HealthcheckGate {
+        timeout_secs: 1,
+        retry_interval_secs: 1,
+        attempt_timeout_secs: 1,
+    });
+
+    let (topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+
+    let event = Event::from("this");
+    in1.send(event.clone()).wait().unwrap();
+
+    sleep_ms(RUNTIME_SLEEP_DURATION);
+
+    rt.block_on(topology.stop()).unwrap();
+
+    let res = out1.collect().wait().unwrap();
+
+    shutdown_on_idle(rt);
+    assert_eq!(vec![event], res);
+}
+
+#[test]
+fn topology_healthcheck_gate_holds_sources_while_unhealthy() {
+    let mut rt = runtime();
+    let (in1, source1) = source();
+    let (out1, sink1) = sink_failing_healthcheck();
+
+    let mut config = Config::empty();
+    config.add_source("in1", source1);
+    config.add_sink("out1", &["in1"], sink1);
+    config.healthcheck_gate = Some(HealthcheckGate {
+        timeout_secs: 1,
+        retry_interval_secs: 1,
+        attempt_timeout_secs: 1,
+    });
+
+    let (topology, crash) = topology::start(config, &mut rt, false).unwrap();
+
+    in1.send(Event::from("this")).wait().unwrap();
+
+    // The source never starts, so once the gate times out the topology aborts
+    // without having delivered anything.
+    assert!(crash.wait().next().is_some());
+    rt.block_on(topology.stop()).unwrap();
+
+    let res = out1.collect().wait().unwrap();
+
+    shutdown_on_idle(rt);
+    assert_eq!(Vec::<Event>::new(), res);
+}